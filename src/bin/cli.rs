@@ -1,9 +1,15 @@
+use std::collections::HashSet;
 use std::env;
 use std::fs::File;
 use std::io::Write;
 
 use pathfinder2::graph;
+use pathfinder2::graph::diagnosis::diagnose_flow;
+use pathfinder2::graph::flow_matrix::flow_matrix;
 use pathfinder2::io;
+use pathfinder2::logging::{self, LogFormat};
+use pathfinder2::safe_db::avatars_json::import_from_avatars_json;
+use pathfinder2::types::edge::GraphModel;
 use pathfinder2::types::Address;
 use pathfinder2::types::U256;
 
@@ -15,15 +21,30 @@ const TRANSFER_THROUGH_SIG: &str = "transferThrough(address[],address[],address[
 const RPC_URL: &str = "https://rpc.gnosischain.com";
 
 fn main() {
+    logging::init(log::LevelFilter::Warn, LogFormat::Text).unwrap();
     let (dotfile, mut args) =
         if env::args().len() >= 2 && env::args().nth_back(1).unwrap() == "--dot" {
             (
-                Some(env::args().last().unwrap()),
+                Some(env::args().next_back().unwrap()),
                 env::args().rev().skip(2).rev().collect::<Vec<_>>(),
             )
         } else {
             (None, env::args().collect::<Vec<_>>())
         };
+    let mut options = graph::FlowOptions {
+        exclude_tokens: take_address_list(&mut args, "--exclude-tokens").unwrap_or_default(),
+        exclude_addresses: take_address_list(&mut args, "--exclude-addresses").unwrap_or_default(),
+        only_tokens: take_address_list(&mut args, "--only-tokens"),
+        only_intermediaries: take_address_list(&mut args, "--only-intermediaries"),
+        accepted_tokens: take_address_list(&mut args, "--accepted-tokens"),
+        ..Default::default()
+    };
+    let diagnose = if let Some(i) = args.iter().position(|a| a == "--diagnose") {
+        args.remove(i);
+        true
+    } else {
+        false
+    };
     let csv = if args.get(1) == Some(&"--csv".to_string()) {
         args = [vec![args[0].clone()], args[2..].to_vec()].concat();
         true
@@ -36,24 +57,43 @@ fn main() {
     } else {
         false
     };
-    if safes && csv {
-        println!("Options --safes and --csv cannot be used together.");
+    let avatars = if args.get(1) == Some(&"--avatars".to_string()) {
+        args = [vec![args[0].clone()], args[2..].to_vec()].concat();
+        true
+    } else {
+        false
+    };
+    if [safes, csv, avatars].iter().filter(|o| **o).count() > 1 {
+        println!("Options --safes, --csv and --avatars cannot be used together.");
         return;
     }
 
     if args.len() < 4 {
-        println!("Usage: cli [--csv] [--safes] <from> <to> <edges.dat> [--dot <dotfile>]");
         println!(
-            "Usage: cli [--csv] [--safes] <from> <to> <edges.dat> <max_hops>  [--dot <dotfile>]"
+            "Usage: cli [--csv | --safes | --avatars] <from> <to> <edges.dat> [--dot <dotfile>]"
+        );
+        println!(
+            "Usage: cli [--csv | --safes | --avatars] <from> <to> <edges.dat> <max_hops>  [--dot <dotfile>]"
         );
         println!(
-            "Usage: cli [--csv] [--safes] <from> <to> <edges.dat> <max_hops> <max_flow> [--dot <dotfile>]"
+            "Usage: cli [--csv | --safes | --avatars] <from> <to> <edges.dat> <max_hops> <max_flow> [--dot <dotfile>]"
         );
         println!(
-            "Usage: cli [--csv] [--safes] <from> <to> <edges.dat> <max_hops> <max_flow> <max_transfers> [--dot <dotfile>]"
+            "Usage: cli [--csv | --safes | --avatars] <from> <to> <edges.dat> <max_hops> <max_flow> <max_transfers> [--dot <dotfile>]"
         );
         println!("Option --csv reads edges.dat in csv format instead of binary.");
         println!("Option --safes reads a safes.dat file instead of an edges.dat file.");
+        println!(
+            "Option --avatars reads a Circles v2 avatars JSON file instead of an edges.dat file."
+        );
+        println!(
+            "Options --exclude-tokens, --exclude-addresses, --only-tokens and --only-intermediaries"
+        );
+        println!(
+            "followed by a comma-separated list of addresses restrict the tokens and intermediaries."
+        );
+        println!("Option --accepted-tokens restricts the tokens the receiver accepts.");
+        println!("Option --diagnose adds the bottlenecks limiting the flow to the output.");
         return;
     }
     let mut max_hops = None;
@@ -79,34 +119,61 @@ fn main() {
         io::read_edges_csv(edges_file)
     } else if safes {
         io::import_from_safes_binary(edges_file).map(|db| db.edges().clone())
+    } else if avatars {
+        import_from_avatars_json(edges_file).map(|db| db.edges().clone())
     } else {
         io::read_edges_binary(edges_file)
     })
     .unwrap_or_else(|_| panic!("Error loading edges/safes from file \"{edges_file}\"."));
     println!("Read {} edges", edges.edge_count());
-    let (flow, transfers) = graph::compute_flow(
+    options.resolve_token_addresses(&edges);
+    let (flow, transfers) = graph::compute_flow_with_options(
         &Address::from(from_str.as_str()),
         &Address::from(to_str.as_str()),
         &edges,
         max_flow,
         max_hops,
         max_transfers,
+        &options,
     );
     println!("Found flow: {}", flow.to_decimal());
     //println!("{:?}", transfers);
 
-    let result = json::object! {
+    let mut result = json::object! {
         maxFlowValue: flow.to_decimal(),
         transferSteps: transfers.iter().enumerate().map(|(i, e)| {
             json::object!{
                 from: e.from.to_checksummed_hex(),
                 to: e.to.to_checksummed_hex(),
-                token: e.token.to_checksummed_hex(),
+                token_owner: e.token.to_checksummed_hex(),
+                token: edges.token_address(&e.token).map(|t| t.to_checksummed_hex()),
                 value: e.capacity.to_decimal(),
                 step: i,
             }
         }).collect::<Vec<_>>()
     };
+    if edges.model() == GraphModel::V2 {
+        result["flowMatrix"] = flow_matrix(
+            &Address::from(from_str.as_str()),
+            &Address::from(to_str.as_str()),
+            &transfers,
+        )
+        .unwrap()
+        .to_json();
+    }
+    if diagnose {
+        let (_, bottlenecks, _) = diagnose_flow(
+            &Address::from(from_str.as_str()),
+            &Address::from(to_str.as_str()),
+            &edges,
+            &options,
+        );
+        result["bottlenecks"] = bottlenecks
+            .iter()
+            .map(|b| b.to_json())
+            .collect::<Vec<_>>()
+            .into();
+    }
     println!("{result}");
 
     // let token_owners = transfers
@@ -141,3 +208,21 @@ fn main() {
         println!("Wrote dotfile {dotfile}.");
     }
 }
+
+/// Removes the option `name` and its value, a comma-separated list of addresses, from `args`.
+fn take_address_list(args: &mut Vec<String>, name: &str) -> Option<HashSet<Address>> {
+    let i = args.iter().position(|a| a == name)?;
+    let Some(list) = args.get(i + 1).cloned() else {
+        panic!("Expected a list of addresses after {name}");
+    };
+    args.drain(i..=i + 1);
+    Some(
+        list.split(',')
+            .filter(|a| !a.is_empty())
+            .map(|a| {
+                a.parse::<Address>()
+                    .unwrap_or_else(|e| panic!("Invalid address in {name}: {e}"))
+            })
+            .collect(),
+    )
+}
@@ -1,60 +1,196 @@
 use std::env;
+use std::fs::{self, File};
+use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process::exit;
 
+use log::LevelFilter;
 use pathfinder2::io::*;
-use pathfinder2::safe_db::safes_json::import_from_safes_json;
+use pathfinder2::logging::{self, LogFormat};
+use pathfinder2::safe_db::avatars_json::import_from_avatars_json_from;
+use pathfinder2::safe_db::db::DB;
+use pathfinder2::safe_db::graphql::GraphQLSource;
+use pathfinder2::safe_db::hub_events::import_from_hub_events_from;
+use pathfinder2::safe_db::safes_json::{export_safes_to_json_to, import_from_safes_json_from};
+use pathfinder2::safe_db::source::{import_from_source, import_from_source_with_checkpoint};
+use pathfinder2::types::edge::EdgeDB;
+
+const OUTPUT_FORMATS: &[&str] = &[
+    "--safes-json",
+    "--safes-bin",
+    "--safes-csv",
+    "--edges-csv",
+    "--edges-bin",
+];
 
 fn main() {
+    logging::init(LevelFilter::Info, LogFormat::Text).unwrap();
+    if env::args().nth(1).as_deref() == Some("fetch") {
+        fetch();
+        return;
+    }
     let input_format = env::args().nth(1).and_then(|op| {
         if matches!(
             op.as_str(),
-            "--safes-json" | "--safes-bin" | "--edges-csv" | "--edges-bin"
+            "--hub-events"
+                | "--safes-json"
+                | "--safes-bin"
+                | "--safes-csv"
+                | "--edges-csv"
+                | "--edges-bin"
+                | "--avatars-json"
         ) {
             Some(op)
         } else {
             None
         }
     });
-    let output_format = env::args().nth(3).and_then(|op| {
-        if matches!(op.as_str(), "--edges-csv" | "--edges-bin") {
-            Some(op)
-        } else {
-            None
-        }
-    });
+    let output_format = env::args()
+        .nth(3)
+        .filter(|op| OUTPUT_FORMATS.contains(&op.as_str()));
     if env::args().len() != 5 || input_format.is_none() || output_format.is_none() {
         println!("Usage: convert <input> <input_file> <output> <output_file>");
+        println!(
+            "   or: convert fetch <subgraph_url> <output> <output_file> [--checkpoint <file>]"
+        );
         println!("  Where <input> is one of:");
+        println!("    --hub-events (Hub and token logs as returned by eth_getLogs)");
         println!("    --safes-json");
         println!("    --safes-bin");
+        println!("    --safes-csv");
         println!("    --edges-csv");
         println!("    --edges-bin");
+        println!("    --avatars-json (Circles v2)");
         println!("  and <output>is one of:");
+        println!("    --safes-json");
+        println!("    --safes-bin");
+        println!("    --safes-csv");
         println!("    --edges-csv");
         println!("    --edges-bin");
+        println!("  Use - as <input_file> or <output_file> to read from stdin or write to stdout.");
+        println!(
+            "  The safes CSV format uses a directory containing organizations.csv, trusts.csv"
+        );
+        println!("  and balances.csv instead of a single file.");
+        println!(
+            "  fetch downloads the safes from a Circles subgraph. With --checkpoint, the safes are"
+        );
+        println!("  also appended to <file> as they arrive, and an interrupted download is resumed from it.");
         return;
     }
+    let (input_format, output_format) = (input_format.unwrap(), output_format.unwrap());
+    if output_format.starts_with("--safes") && input_format.starts_with("--edges") {
+        eprintln!("Cannot convert an edge database into a safe database.");
+        exit(1);
+    }
+    if output_format.starts_with("--safes") && input_format == "--avatars-json" {
+        eprintln!("Cannot convert a Circles v2 avatar database into a safe database.");
+        exit(1);
+    }
 
     let input_file = env::args().nth(2).unwrap();
-    let edges = match input_format.unwrap().as_str() {
-        "--safes-json" => {
-            let safes = import_from_safes_json(&input_file);
-            safes.edges().clone()
-        }
-        "--safes-bin" => {
-            let safes = import_from_safes_binary(&input_file).unwrap();
-            safes.edges().clone()
-        }
-        "--edges-csv" => read_edges_csv(&input_file).unwrap(),
-        "--edges-bin" => read_edges_binary(&input_file).unwrap(),
+    let safes = match input_format.as_str() {
+        "--hub-events" => Some(import_from_hub_events_from(open_input(&input_file)).unwrap()),
+        "--safes-json" => Some(import_from_safes_json_from(open_input(&input_file)).unwrap()),
+        "--safes-bin" => Some(import_from_safes_binary_from(open_input(&input_file)).unwrap()),
+        "--safes-csv" => Some(import_from_safes_csv(&input_file).unwrap()),
+        _ => None,
+    };
+    let edges = match (&safes, input_format.as_str()) {
+        (Some(safes), _) => safes.edges().clone(),
+        (None, "--edges-csv") => read_edges_csv_from(open_input(&input_file)).unwrap(),
+        (None, "--edges-bin") => read_edges_binary_from(open_input(&input_file)).unwrap(),
+        (None, "--avatars-json") => import_from_avatars_json_from(open_input(&input_file))
+            .unwrap()
+            .edges()
+            .clone(),
         _ => unreachable!(),
     };
-    println!("Imported {} edges.", edges.edge_count());
+    if let Some(safes) = &safes {
+        eprintln!("Imported {} safes.", safes.safes().len());
+    }
+    eprintln!("Imported {} edges.", edges.edge_count());
 
-    let output_file = env::args().nth(4).unwrap();
-    match output_format.unwrap().as_str() {
-        "--edges-csv" => write_edges_csv(&edges, &output_file).unwrap(),
-        "--edges-bin" => write_edges_binary(&edges, &output_file).unwrap(),
-        _ => unreachable!(),
+    write_output(
+        &output_format,
+        &env::args().nth(4).unwrap(),
+        safes.as_ref(),
+        &edges,
+    );
+}
+
+/// `convert fetch <subgraph_url> <output> <output_file> [--checkpoint <file>]`
+fn fetch() {
+    let mut args = env::args().skip(2).collect::<Vec<_>>();
+    let checkpoint = match args.iter().position(|a| a == "--checkpoint") {
+        Some(i) if i + 1 < args.len() => Some(args.drain(i..i + 2).nth(1).unwrap()),
+        _ => None,
+    };
+    let [url, output_format, output_file] = &args[..] else {
+        eprintln!(
+            "Usage: convert fetch <subgraph_url> <output> <output_file> [--checkpoint <file>]"
+        );
+        exit(1);
+    };
+    if !OUTPUT_FORMATS.contains(&output_format.as_str()) {
+        eprintln!("Invalid output format: {output_format}");
+        exit(1);
+    }
+    let safes = match &checkpoint {
+        Some(checkpoint) => import_from_source_with_checkpoint(Path::new(checkpoint), |last_id| {
+            if !last_id.is_empty() {
+                eprintln!("Resuming after {last_id}.");
+            }
+            GraphQLSource::new(url).resume_after(last_id)
+        }),
+        None => import_from_source(&mut GraphQLSource::new(url)),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("Error fetching safes: {e}");
+        exit(1);
+    });
+    eprintln!("Imported {} safes.", safes.safes().len());
+    write_output(
+        output_format,
+        output_file,
+        Some(&safes),
+        &safes.edges().clone(),
+    );
+    // The download is complete, a new one should start from scratch.
+    if let Some(checkpoint) = checkpoint {
+        fs::remove_file(checkpoint).unwrap();
+    }
+}
+
+fn write_output(output_format: &str, output_file: &str, safes: Option<&DB>, edges: &EdgeDB) {
+    if output_format == "--safes-csv" {
+        export_safes_to_csv(safes.unwrap(), output_file).unwrap();
+    } else {
+        let mut output = create_output(output_file);
+        match output_format {
+            "--safes-json" => export_safes_to_json_to(safes.unwrap(), &mut output).unwrap(),
+            "--safes-bin" => export_safes_to_binary_to(safes.unwrap(), &mut output).unwrap(),
+            "--edges-csv" => write_edges_csv_to(edges, &mut output).unwrap(),
+            "--edges-bin" => write_edges_binary_to(edges, &mut output).unwrap(),
+            _ => unreachable!(),
+        }
+        output.flush().unwrap();
+    }
+    eprintln!("Export done.");
+}
+
+fn open_input(file: &str) -> Box<dyn BufRead> {
+    if file == "-" {
+        Box::new(BufReader::new(stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(file).unwrap()))
+    }
+}
+
+fn create_output(file: &str) -> Box<dyn Write> {
+    if file == "-" {
+        Box::new(BufWriter::new(stdout()))
+    } else {
+        Box::new(BufWriter::new(File::create(file).unwrap()))
     }
-    println!("Export done.");
 }
@@ -1,23 +1,20 @@
 use std::env;
+use std::process::exit;
 
+use pathfinder2::logging;
 use pathfinder2::server;
+use pathfinder2::server::config::Config;
 
 fn main() {
-    let listen_at = env::args()
-        .nth(1)
-        .unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let config =
+        Config::load(env::args().skip(1), |name| env::var(name).ok()).unwrap_or_else(|e| {
+            eprintln!("Invalid configuration: {e}");
+            exit(1);
+        });
+    logging::init(config.log_level, config.log_format).unwrap();
 
-    let queue_size = env::args()
-        .nth(2)
-        .unwrap_or_else(|| "10".to_string())
-        .parse::<usize>()
-        .unwrap();
-
-    let thread_count = env::args()
-        .nth(3)
-        .unwrap_or_else(|| "4".to_string())
-        .parse::<u64>()
-        .unwrap();
-
-    server::start_server(&listen_at, queue_size, thread_count);
+    if let Err(e) = server::start_server(config) {
+        log::error!("{e}");
+        exit(1);
+    }
 }
@@ -1,13 +1,94 @@
+use crate::graph::flow::FlowOptions;
 use crate::graph::Node;
-use crate::types::edge::EdgeDB;
-use crate::types::{Edge, U256};
+use crate::types::demurrage::Demurrage;
+use crate::types::edge::{EdgeDB, GraphModel};
+use crate::types::{Address, Edge, U256};
 use std::cmp::{max, Reverse};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+/// The flow network of an edge database without restrictions, computed once
+/// and shared by all flow computations on that database.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FlowNetwork {
+    adjacencies: HashMap<Node, HashMap<Node, U256>>,
+}
+
+impl FlowNetwork {
+    pub fn new(edges: &EdgeDB) -> FlowNetwork {
+        let nodes = edges
+            .edges()
+            .iter()
+            .flat_map(|edge| nodes_of(edges, edge))
+            .collect::<HashSet<_>>();
+        let mut network = FlowNetwork::default();
+        network.recompute(edges, nodes);
+        network
+    }
+
+    /// Updates the network after `changed_edges` and the balances of
+    /// `changed_balances` (holder, token owner) were changed in `edges`.
+    pub fn update(
+        &mut self,
+        edges: &EdgeDB,
+        changed_edges: &[Edge],
+        changed_balances: &[(Address, Address)],
+    ) {
+        let nodes = changed_edges
+            .iter()
+            .flat_map(|edge| nodes_of(edges, edge))
+            .chain(
+                changed_balances
+                    .iter()
+                    .map(|(holder, _)| Node::Node(*holder)),
+            )
+            .collect::<HashSet<_>>();
+        self.recompute(edges, nodes);
+    }
+
+    /// Recomputes the capacities of the edges leaving `nodes`.
+    fn recompute(&mut self, edges: &EdgeDB, nodes: HashSet<Node>) {
+        let options = FlowOptions::default();
+        for node in nodes {
+            // The source and the sink are only used by restrictions.
+            let capacities = capacities(
+                edges,
+                &options,
+                &Address::default(),
+                &Address::default(),
+                &node,
+            );
+            if capacities.is_empty() {
+                self.adjacencies.remove(&node);
+            } else {
+                self.adjacencies.insert(node, capacities);
+            }
+        }
+    }
+}
+
+/// The nodes of the flow network whose capacities depend on `edge`.
+fn nodes_of(edges: &EdgeDB, edge: &Edge) -> [Node; 3] {
+    let intermediate = if edges.is_mint(edge) {
+        Node::MintNode(edge.from)
+    } else {
+        balance_node(edge)
+    };
+    [Node::Node(edge.from), intermediate, trust_node(edge)]
+}
 
 pub struct Adjacencies<'a> {
     edges: &'a EdgeDB,
+    /// The shared flow network of `edges`, used unless the options restrict the edges.
+    network: Option<&'a FlowNetwork>,
+    /// Capacities added to those of `edges`, e.g. for hypothetical trusts.
+    extra_capacities: Option<&'a HashMap<Node, HashMap<Node, U256>>>,
     lazy_adjacencies: HashMap<Node, HashMap<Node, U256>>,
     capacity_adjustments: HashMap<Node, HashMap<Node, U256>>,
+    /// Conversion of the capacities to the requested point in time, if any.
+    demurrage: Option<Demurrage>,
+    source: Address,
+    sink: Address,
+    options: &'a FlowOptions,
 }
 
 // fn pseudo_node(edge: Edge) -> Node {
@@ -30,14 +111,46 @@ fn trust_node(edge: &Edge) -> Node {
 // }
 
 impl<'a> Adjacencies<'a> {
-    pub fn new(edges: &'a EdgeDB) -> Self {
+    pub fn new(
+        edges: &'a EdgeDB,
+        source: &Address,
+        sink: &Address,
+        options: &'a FlowOptions,
+    ) -> Self {
+        let demurrage = match (edges.timestamp(), options.as_of) {
+            (Some(timestamp), Some(as_of)) => Some(Demurrage::between(timestamp, as_of)),
+            _ => None,
+        };
         Adjacencies {
             edges,
+            network: None,
+            extra_capacities: None,
             lazy_adjacencies: HashMap::new(),
             capacity_adjustments: HashMap::new(),
+            demurrage,
+            source: *source,
+            sink: *sink,
+            options,
         }
     }
 
+    /// Uses the shared flow network of the edges instead of computing the capacities.
+    pub fn with_network(mut self, network: &'a FlowNetwork) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Adds `extra` to the capacities of the edges, where the larger one
+    /// is used if an edge already has a capacity.
+    pub fn with_extra_capacities(mut self, extra: &'a HashMap<Node, HashMap<Node, U256>>) -> Self {
+        self.extra_capacities = Some(extra);
+        self
+    }
+
+    pub fn deadline_passed(&self) -> bool {
+        self.options.deadline_passed()
+    }
+
     pub fn outgoing_edges_sorted_by_capacity(&mut self, from: &Node) -> Vec<(Node, U256)> {
         let mut adjacencies = self.adjacencies_from(from);
         if let Some(adjustments) = self.capacity_adjustments.get(from) {
@@ -49,7 +162,16 @@ impl<'a> Adjacencies<'a> {
             .into_iter()
             .filter(|(_, cap)| *cap != U256::from(0))
             .collect::<Vec<(Node, U256)>>();
-        result.sort_unstable_by_key(|(addr, capacity)| (Reverse(*capacity), addr.clone()));
+        // Nodes of preferred tokens first, then by capacity.
+        let weight = |node: &Node| match node {
+            Node::BalanceNode(_, token) | Node::TrustNode(_, token) => {
+                self.options.token_weight(token)
+            }
+            _ => 0,
+        };
+        result.sort_unstable_by_key(|(addr, capacity)| {
+            (Reverse(weight(addr)), Reverse(*capacity), addr.clone())
+        });
         result
     }
 
@@ -62,6 +184,12 @@ impl<'a> Adjacencies<'a> {
             .or_default() += adjustment;
     }
 
+    /// The capacities of the edges leaving `from`, without the adjustments
+    /// made by the flow sent so far.
+    pub fn capacities_from(&mut self, from: &Node) -> HashMap<Node, U256> {
+        self.adjacencies_from(from)
+    }
+
     #[allow(clippy::wrong_self_convention)]
     pub fn is_adjacent(&mut self, from: &Node, to: &Node) -> bool {
         // TODO More efficiently?
@@ -73,53 +201,105 @@ impl<'a> Adjacencies<'a> {
     }
 
     fn adjacencies_from(&mut self, from: &Node) -> HashMap<Node, U256> {
-        self.lazy_adjacencies
-            .entry(from.clone())
-            .or_insert_with(|| {
-                let mut result: HashMap<Node, U256> = HashMap::new();
-                // Plain edges are (from, to, token) labeled with capacity
-                match from {
-                    Node::Node(from) => {
-                        for edge in self.edges.outgoing(from) {
-                            // One edge from "from" to "from x token" with a capacity
-                            // as the max over all "to" addresses (the balance of the sender)
-                            result
-                                .entry(balance_node(edge))
-                                .and_modify(|c| {
-                                    if edge.capacity > *c {
-                                        *c = edge.capacity;
-                                    }
-                                })
-                                .or_insert(edge.capacity);
-                        }
-                    }
-                    Node::BalanceNode(from, token) => {
-                        for edge in self.edges.outgoing(from) {
-                            // The actual capacity of the edge / the send limit.
-                            if edge.from == *from && edge.token == *token {
-                                result.insert(trust_node(edge), edge.capacity);
-                            }
-                        }
+        let shared = self.network.filter(|_| !self.options.restricts_edges());
+        let mut result = match shared {
+            Some(network) => network.adjacencies.get(from).cloned().unwrap_or_default(),
+            None => self
+                .lazy_adjacencies
+                .entry(from.clone())
+                .or_insert_with(|| {
+                    capacities(self.edges, self.options, &self.source, &self.sink, from)
+                })
+                .clone(),
+        };
+        if let Some(extra) = self.extra_capacities.and_then(|extra| extra.get(from)) {
+            for (node, c) in extra {
+                let capacity = result.entry(node.clone()).or_default();
+                *capacity = max(*capacity, *c);
+            }
+        }
+        if let Some(demurrage) = &self.demurrage {
+            for capacity in result.values_mut() {
+                *capacity = demurrage.apply(*capacity);
+            }
+        }
+        result
+    }
+}
+
+/// The capacities of the edges leaving `from` in the flow network of `edges`
+/// for a transfer from `source` to `sink`, before demurrage.
+fn capacities(
+    edges: &EdgeDB,
+    options: &FlowOptions,
+    source: &Address,
+    sink: &Address,
+    from: &Node,
+) -> HashMap<Node, U256> {
+    let mut result: HashMap<Node, U256> = HashMap::new();
+    let allowed = |edge: &&Edge| options.allows(edge, source, sink);
+    // Plain edges are (from, to, token) labeled with capacity
+    match from {
+        Node::Node(from) => {
+            for edge in edges.outgoing(from).into_iter().filter(allowed) {
+                if edges.is_mint(edge) {
+                    continue;
+                }
+                // One edge from "from" to "from x token" with the balance of the sender
+                // as capacity. If the balance is not known, we use the max over all "to"
+                // addresses instead.
+                match edges.balance(from, &edge.token) {
+                    Some(balance) => {
+                        result.insert(balance_node(edge), balance);
                     }
-                    Node::TrustNode(to, token) => {
-                        let is_return_to_owner = *to == *token;
-                        // If token is to's token: send back to owner, infinite capacity.
-                        // Otherwise, the max of the incoming edges (the trust limit)
-                        let mut capacity = U256::from(0);
-                        for edge in self.edges.incoming(to) {
-                            if edge.token == *token {
-                                if is_return_to_owner {
-                                    capacity += edge.capacity
-                                } else {
-                                    capacity = max(capacity, edge.capacity)
+                    None => {
+                        result
+                            .entry(balance_node(edge))
+                            .and_modify(|c| {
+                                if edge.capacity > *c {
+                                    *c = edge.capacity;
                                 }
-                            }
-                            result.insert(Node::Node(*to), capacity);
-                        }
+                            })
+                            .or_insert(edge.capacity);
                     }
                 }
-                result
-            })
-            .clone()
+            }
+        }
+        Node::BalanceNode(from, token) => {
+            for edge in edges.outgoing(from).into_iter().filter(allowed) {
+                // The actual capacity of the edge / the send limit.
+                if edge.from == *from && edge.token == *token && !edges.is_mint(edge) {
+                    result.insert(trust_node(edge), edge.capacity);
+                }
+            }
+        }
+        Node::TrustNode(to, token) => {
+            // In v2, trust is not limited, so the receiver accepts everything.
+            let is_unlimited = *to == *token || edges.model() == GraphModel::V2;
+            // If token is to's token: send back to owner, infinite capacity.
+            // Otherwise, the max of the incoming edges (the trust limit)
+            let mut capacity = U256::from(0);
+            for edge in edges.incoming(to).into_iter().filter(allowed) {
+                if edge.token != *token {
+                    continue;
+                }
+                if edges.is_mint(edge) {
+                    // The group can mint from this collateral.
+                    result.insert(Node::MintNode(*to), edge.capacity);
+                } else {
+                    if is_unlimited {
+                        capacity = capacity.saturating_add(edge.capacity)
+                    } else {
+                        capacity = max(capacity, edge.capacity)
+                    }
+                    result.insert(Node::Node(*to), capacity);
+                }
+            }
+        }
+        Node::MintNode(group) => {
+            // Minted tokens are not limited.
+            result.insert(Node::BalanceNode(*group, *group), U256::MAX);
+        }
     }
+    result
 }
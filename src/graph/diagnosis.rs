@@ -0,0 +1,221 @@
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use json::JsonValue;
+
+use crate::graph::adjacencies::Adjacencies;
+use crate::graph::flow::{max_flow, FlowOptions};
+use crate::graph::Node;
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, U256};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BottleneckKind {
+    /// The sender `from` does not hold more `token` tokens.
+    BalanceLimit,
+    /// `to` does not accept more `token` tokens, either from `from`
+    /// (the send limit) or in total (the trust limit of `to`).
+    TrustLimit,
+    /// The receiver `to` does not trust `token`, but `from` could
+    /// send at least `amount` of it.
+    MissingTrust,
+}
+
+impl BottleneckKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BottleneckKind::BalanceLimit => "balance_limit",
+            BottleneckKind::TrustLimit => "trust_limit",
+            BottleneckKind::MissingTrust => "missing_trust",
+        }
+    }
+}
+
+/// An edge of the minimum cut between the source and the sink,
+/// or a trust that would increase the maximum flow if it was added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bottleneck {
+    pub kind: BottleneckKind,
+    pub from: Address,
+    /// The receiver, None for balance limits.
+    pub to: Option<Address>,
+    pub token: Address,
+    pub amount: U256,
+}
+
+impl Bottleneck {
+    pub fn to_json(&self) -> JsonValue {
+        json::object! {
+            kind: self.kind.name(),
+            from: self.from.to_checksummed_hex(),
+            to: self.to.map(|to| to.to_checksummed_hex()),
+            token_owner: self.token.to_checksummed_hex(),
+            amount: self.amount.to_decimal(),
+        }
+    }
+}
+
+/// Explains the maximum flow from `source` to `sink`: Computes the maximum flow
+/// without limiting the distance and returns it together with the edges of the
+/// minimum cut in the residual network and the trusts missing at the sink.
+/// If the deadline of the options passes, returns the flow found so far
+/// without bottlenecks and true as the last value.
+pub fn diagnose_flow(
+    source: &Address,
+    sink: &Address,
+    edges: &EdgeDB,
+    options: &FlowOptions,
+) -> (U256, Vec<Bottleneck>, bool) {
+    let mut adjacencies = Adjacencies::new(edges, source, sink, options);
+    let (flow, _, timed_out) = max_flow(source, sink, &mut adjacencies, None, U256::MAX);
+    if timed_out {
+        return (flow, vec![], true);
+    }
+
+    // The nodes still reachable from the source in the residual network,
+    // with the amount that can still reach them.
+    let mut reachable = HashMap::new();
+    let mut queue = VecDeque::new();
+    reachable.insert(Node::Node(*source), U256::MAX);
+    queue.push_back((Node::Node(*source), U256::MAX));
+    while let Some((node, amount)) = queue.pop_front() {
+        for (target, capacity) in adjacencies.outgoing_edges_sorted_by_capacity(&node) {
+            if !reachable.contains_key(&target) {
+                let amount = min(amount, capacity);
+                reachable.insert(target.clone(), amount);
+                queue.push_back((target, amount));
+            }
+        }
+    }
+
+    let mut bottlenecks = vec![];
+    for node in reachable.keys() {
+        for (target, capacity) in adjacencies.capacities_from(node) {
+            if capacity == U256::from(0) || reachable.contains_key(&target) {
+                continue;
+            }
+            let (kind, from, to, token) = match (node, &target) {
+                (Node::Node(from), Node::BalanceNode(_, token)) => {
+                    (BottleneckKind::BalanceLimit, *from, None, *token)
+                }
+                (Node::BalanceNode(from, token), Node::TrustNode(to, _)) => {
+                    (BottleneckKind::TrustLimit, *from, Some(*to), *token)
+                }
+                // The total of all senders is limited, so there is no single sender.
+                (Node::TrustNode(to, token), _) => {
+                    (BottleneckKind::TrustLimit, *to, Some(*to), *token)
+                }
+                _ => continue,
+            };
+            bottlenecks.push(Bottleneck {
+                kind,
+                from,
+                to,
+                token,
+                amount: capacity,
+            });
+        }
+    }
+
+    // For each token that can still be sent but is not trusted by the sink,
+    // the holder that can send the most of it.
+    let trusted = edges
+        .incoming(sink)
+        .iter()
+        .map(|e| e.token)
+        .collect::<Vec<_>>();
+    let mut missing: BTreeMap<Address, (Address, U256)> = BTreeMap::new();
+    for (node, amount) in &reachable {
+        if let Node::BalanceNode(holder, token) = node {
+            let edge = Edge {
+                from: *holder,
+                to: *sink,
+                token: *token,
+                capacity: *amount,
+            };
+            if holder == sink
+                || trusted.contains(token)
+                || !options.allows(&edge, source, sink)
+                || *amount == U256::from(0)
+            {
+                continue;
+            }
+            let best = missing.entry(*token).or_insert((*holder, *amount));
+            if (*amount, *holder) > (best.1, best.0) {
+                *best = (*holder, *amount);
+            }
+        }
+    }
+    bottlenecks.extend(
+        missing
+            .into_iter()
+            .map(|(token, (from, amount))| Bottleneck {
+                kind: BottleneckKind::MissingTrust,
+                from,
+                to: Some(*sink),
+                token,
+                amount,
+            }),
+    );
+    bottlenecks.sort_by_key(|b| (b.kind, b.from, b.to, b.token));
+    (flow, bottlenecks, false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::test_util::{addresses, edge};
+
+    #[test]
+    fn min_cut() {
+        let (a, b, c, d, ..) = addresses();
+        // a can send 30 of its tokens, b accepts 10 of them and c 20.
+        // b can send 100 of its tokens to d, c cannot send anything to d.
+        let mut edges = EdgeDB::new(vec![
+            edge(a, b, a, 10),
+            edge(a, c, a, 20),
+            edge(b, d, b, 100),
+        ]);
+        edges.update_balance(a, a, U256::from(30));
+        let (flow, bottlenecks, _) = diagnose_flow(&a, &d, &edges, &FlowOptions::default());
+        assert_eq!(flow, U256::from(10));
+        assert_eq!(
+            bottlenecks,
+            vec![
+                Bottleneck {
+                    kind: BottleneckKind::TrustLimit,
+                    from: a,
+                    to: Some(b),
+                    token: a,
+                    amount: U256::from(10),
+                },
+                Bottleneck {
+                    kind: BottleneckKind::MissingTrust,
+                    from: a,
+                    to: Some(d),
+                    token: a,
+                    amount: U256::from(20),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn balance_limit() {
+        let (a, b, ..) = addresses();
+        let mut edges = EdgeDB::new(vec![edge(a, b, a, 100)]);
+        edges.update_balance(a, a, U256::from(7));
+        let (flow, bottlenecks, _) = diagnose_flow(&a, &b, &edges, &FlowOptions::default());
+        assert_eq!(flow, U256::from(7));
+        assert_eq!(
+            bottlenecks,
+            vec![Bottleneck {
+                kind: BottleneckKind::BalanceLimit,
+                from: a,
+                to: None,
+                token: a,
+                amount: U256::from(7),
+            }]
+        );
+    }
+}
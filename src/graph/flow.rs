@@ -1,4 +1,4 @@
-use crate::graph::adjacencies::Adjacencies;
+use crate::graph::adjacencies::{Adjacencies, FlowNetwork};
 use crate::graph::{as_trust_node, Node};
 use crate::types::edge::EdgeDB;
 use crate::types::{Address, Edge, U256};
@@ -6,6 +6,102 @@ use std::cmp::min;
 use std::collections::{BTreeMap, HashSet};
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Write;
+use std::time::Instant;
+
+/// Additional options for `compute_flow_with_options`.
+#[derive(Clone, Debug, Default)]
+pub struct FlowOptions {
+    /// Computes the flow as of this time (unix timestamp), i.e. applies demurrage
+    /// to the balances of the graph, which refer to `EdgeDB::timestamp`.
+    /// The amounts of the transfers are the amounts at that time.
+    /// Trusts expiring in between are not taken into account.
+    pub as_of: Option<u64>,
+    /// Tokens that cannot be used.
+    pub exclude_tokens: HashSet<Address>,
+    /// Addresses that cannot be used as intermediaries.
+    pub exclude_addresses: HashSet<Address>,
+    /// If set, only these tokens can be used.
+    pub only_tokens: Option<HashSet<Address>>,
+    /// If set, only these addresses can be used as intermediaries.
+    pub only_intermediaries: Option<HashSet<Address>>,
+    /// Weights of tokens (default 0). Tokens with higher weights are
+    /// preferred, and if the maximum flow exceeds the requested amount,
+    /// the sender keeps its balances of the tokens with lower weights.
+    pub token_weights: HashMap<Address, i64>,
+    /// If set, the sink only receives these tokens.
+    pub accepted_tokens: Option<HashSet<Address>>,
+    /// If set, the computation of the maximum flow stops at this time
+    /// and continues with the flow found so far.
+    pub deadline: Option<Instant>,
+}
+
+impl FlowOptions {
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Replaces the token contract addresses in the token filters and weights
+    /// by the owners of the tokens, which is what the edges refer to.
+    pub fn resolve_token_addresses(&mut self, edges: &EdgeDB) {
+        let resolve = |tokens: &mut HashSet<Address>| {
+            *tokens = tokens
+                .iter()
+                .map(|token| *edges.token_owner(token).unwrap_or(token))
+                .collect();
+        };
+        resolve(&mut self.exclude_tokens);
+        if let Some(tokens) = &mut self.only_tokens {
+            resolve(tokens);
+        }
+        if let Some(tokens) = &mut self.accepted_tokens {
+            resolve(tokens);
+        }
+        self.token_weights = self
+            .token_weights
+            .iter()
+            .map(|(token, weight)| (*edges.token_owner(token).unwrap_or(token), *weight))
+            .collect();
+    }
+
+    pub fn token_weight(&self, token: &Address) -> i64 {
+        self.token_weights.get(token).copied().unwrap_or_default()
+    }
+
+    /// @returns true if the options exclude some edges from the flow network.
+    pub fn restricts_edges(&self) -> bool {
+        !self.exclude_tokens.is_empty()
+            || !self.exclude_addresses.is_empty()
+            || self.only_tokens.is_some()
+            || self.only_intermediaries.is_some()
+            || self.accepted_tokens.is_some()
+    }
+
+    /// @returns true if the filters allow `edge` to be used
+    /// for a transfer from `source` to `sink`.
+    pub fn allows(&self, edge: &Edge, source: &Address, sink: &Address) -> bool {
+        let allows_token = !self.exclude_tokens.contains(&edge.token)
+            && self
+                .only_tokens
+                .as_ref()
+                .is_none_or(|tokens| tokens.contains(&edge.token));
+        let allows_address = |address: &Address| {
+            address == source
+                || address == sink
+                || (!self.exclude_addresses.contains(address)
+                    && self
+                        .only_intermediaries
+                        .as_ref()
+                        .is_none_or(|addresses| addresses.contains(address)))
+        };
+        let accepted = edge.to != *sink
+            || self
+                .accepted_tokens
+                .as_ref()
+                .is_none_or(|tokens| tokens.contains(&edge.token));
+        allows_token && accepted && allows_address(&edge.from) && allows_address(&edge.to)
+    }
+}
 
 pub fn compute_flow(
     source: &Address,
@@ -15,54 +111,212 @@ pub fn compute_flow(
     max_distance: Option<u64>,
     max_transfers: Option<u64>,
 ) -> (U256, Vec<Edge>) {
-    let mut adjacencies = Adjacencies::new(edges);
-    let mut used_edges: HashMap<Node, HashMap<Node, U256>> = HashMap::new();
+    compute_flow_with_options(
+        source,
+        sink,
+        edges,
+        requested_flow,
+        max_distance,
+        max_transfers,
+        &FlowOptions::default(),
+    )
+}
 
-    let mut flow = U256::default();
-    loop {
-        let (new_flow, parents) = augmenting_path(source, sink, &mut adjacencies, max_distance);
-        if new_flow == U256::default() {
-            break;
-        }
-        flow += new_flow;
-        for window in parents.windows(2) {
-            if let [node, prev] = window {
-                adjacencies.adjust_capacity(prev, node, -new_flow);
-                adjacencies.adjust_capacity(node, prev, new_flow);
-                if adjacencies.is_adjacent(node, prev) {
-                    *used_edges
-                        .entry(node.clone())
-                        .or_default()
-                        .entry(prev.clone())
-                        .or_default() -= new_flow;
-                } else {
-                    *used_edges
-                        .entry(prev.clone())
-                        .or_default()
-                        .entry(node.clone())
-                        .or_default() += new_flow;
-                }
-            } else {
-                panic!();
-            }
-        }
+/// Computes only the amount that can be sent from `source` to `sink`,
+/// up to `requested_flow`, without computing the transfers.
+/// Returns the amount and whether the deadline of the options passed,
+/// in which case it is only the amount found so far.
+pub fn compute_max_flow(
+    source: &Address,
+    sink: &Address,
+    edges: &EdgeDB,
+    requested_flow: U256,
+    max_distance: Option<u64>,
+    options: &FlowOptions,
+) -> (U256, bool) {
+    let adjacencies = Adjacencies::new(edges, source, sink, options);
+    max_flow_with_adjacencies(source, sink, adjacencies, requested_flow, max_distance)
+}
+
+/// The maximum flow between two addresses before it is reduced to
+/// the requested amount, which does not depend on that amount.
+#[derive(Debug, Clone)]
+pub struct MaxFlow {
+    flow: U256,
+    used_edges: HashMap<Node, HashMap<Node, U256>>,
+    /// True if the computation stopped at the deadline, i.e. the flow
+    /// is not necessarily the maximum.
+    timed_out: bool,
+}
+
+impl MaxFlow {
+    pub fn flow(&self) -> U256 {
+        self.flow
     }
 
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+}
+
+impl FlowNetwork {
+    /// Computes the maximum flow (without distance limit if `max_distance` is None),
+    /// to be reduced to the requested amount by `transfers_from_max_flow`.
+    pub fn max_flow(
+        &self,
+        source: &Address,
+        sink: &Address,
+        edges: &EdgeDB,
+        max_distance: Option<u64>,
+        options: &FlowOptions,
+    ) -> MaxFlow {
+        let adjacencies = Adjacencies::new(edges, source, sink, options).with_network(self);
+        max_flow_with_used_edges(source, sink, adjacencies, max_distance)
+    }
+
+    /// Like `compute_flow_with_options`, for the edges this network was built from.
+    #[allow(clippy::too_many_arguments)]
+    pub fn compute_flow(
+        &self,
+        source: &Address,
+        sink: &Address,
+        edges: &EdgeDB,
+        requested_flow: U256,
+        max_distance: Option<u64>,
+        max_transfers: Option<u64>,
+        options: &FlowOptions,
+    ) -> (U256, Vec<Edge>) {
+        let adjacencies = Adjacencies::new(edges, source, sink, options).with_network(self);
+        flow_with_adjacencies(
+            source,
+            sink,
+            adjacencies,
+            requested_flow,
+            max_distance,
+            max_transfers,
+            options,
+        )
+    }
+
+    /// Like `compute_max_flow`, for the edges this network was built from.
+    pub fn compute_max_flow(
+        &self,
+        source: &Address,
+        sink: &Address,
+        edges: &EdgeDB,
+        requested_flow: U256,
+        max_distance: Option<u64>,
+        options: &FlowOptions,
+    ) -> (U256, bool) {
+        let adjacencies = Adjacencies::new(edges, source, sink, options).with_network(self);
+        max_flow_with_adjacencies(source, sink, adjacencies, requested_flow, max_distance)
+    }
+}
+
+fn max_flow_with_adjacencies(
+    source: &Address,
+    sink: &Address,
+    mut adjacencies: Adjacencies,
+    requested_flow: U256,
+    max_distance: Option<u64>,
+) -> (U256, bool) {
+    let (flow, _, timed_out) =
+        max_flow(source, sink, &mut adjacencies, max_distance, requested_flow);
+    (min(flow, requested_flow), timed_out)
+}
+
+pub fn compute_flow_with_options(
+    source: &Address,
+    sink: &Address,
+    edges: &EdgeDB,
+    requested_flow: U256,
+    max_distance: Option<u64>,
+    max_transfers: Option<u64>,
+    options: &FlowOptions,
+) -> (U256, Vec<Edge>) {
+    let adjacencies = Adjacencies::new(edges, source, sink, options);
+    flow_with_adjacencies(
+        source,
+        sink,
+        adjacencies,
+        requested_flow,
+        max_distance,
+        max_transfers,
+        options,
+    )
+}
+
+fn flow_with_adjacencies(
+    source: &Address,
+    sink: &Address,
+    adjacencies: Adjacencies,
+    requested_flow: U256,
+    max_distance: Option<u64>,
+    max_transfers: Option<u64>,
+    options: &FlowOptions,
+) -> (U256, Vec<Edge>) {
+    let max_flow = max_flow_with_used_edges(source, sink, adjacencies, max_distance);
+    transfers_from_max_flow(
+        source,
+        sink,
+        max_flow,
+        requested_flow,
+        max_transfers,
+        options,
+    )
+}
+
+fn max_flow_with_used_edges(
+    source: &Address,
+    sink: &Address,
+    mut adjacencies: Adjacencies,
+    max_distance: Option<u64>,
+) -> MaxFlow {
+    let (flow, mut used_edges, timed_out) =
+        max_flow(source, sink, &mut adjacencies, max_distance, U256::MAX);
+
     used_edges.retain(|_, out| {
         out.retain(|_, c| *c != U256::from(0));
         !out.is_empty()
     });
+    let used_edges = merge_mint_nodes(used_edges);
 
-    println!("Max flow: {}", flow.to_decimal());
+    log::debug!("Max flow: {}", flow.to_decimal());
+    MaxFlow {
+        flow,
+        used_edges,
+        timed_out,
+    }
+}
+
+/// Computes the transfers of `requested_flow` (or less) from the maximum flow
+/// computed by `FlowNetwork::max_flow`.
+pub fn transfers_from_max_flow(
+    source: &Address,
+    sink: &Address,
+    max_flow: MaxFlow,
+    requested_flow: U256,
+    max_transfers: Option<u64>,
+    options: &FlowOptions,
+) -> (U256, Vec<Edge>) {
+    let MaxFlow {
+        mut flow,
+        mut used_edges,
+        ..
+    } = max_flow;
 
     if flow > requested_flow {
-        let still_to_prune = prune_flow(source, sink, flow - requested_flow, &mut used_edges);
+        let mut to_prune = flow - requested_flow;
+        if !options.token_weights.is_empty() {
+            to_prune = prune_by_token_weight(source, to_prune, &mut used_edges, options);
+        }
+        let still_to_prune = prune_flow(source, sink, to_prune, &mut used_edges);
         flow = requested_flow + still_to_prune;
     }
 
     if let Some(max_transfers) = max_transfers {
         let lost = reduce_transfers(max_transfers * 3, &mut used_edges);
-        println!(
+        log::debug!(
             "Capacity lost by transfer count reduction: {}",
             lost.to_decimal_fraction()
         );
@@ -74,9 +328,9 @@ pub fn compute_flow(
     } else {
         extract_transfers(source, sink, &flow, used_edges)
     };
-    println!("Num transfers: {}", transfers.len());
-    let simplified_transfers = simplify_transfers(transfers);
-    println!("After simplification: {}", simplified_transfers.len());
+    log::debug!("Num transfers: {}", transfers.len());
+    let simplified_transfers = simplify_transfers(transfers, options);
+    log::debug!("After simplification: {}", simplified_transfers.len());
     let sorted_transfers = sort_transfers(simplified_transfers);
     (flow, sorted_transfers)
 }
@@ -113,6 +367,54 @@ pub fn transfers_to_dot(edges: &Vec<Edge>) -> String {
     out
 }
 
+/// Computes the maximum flow from `source` to `sink`, leaving the residual
+/// network in `adjacencies`. Stops early once the flow reaches `limit`
+/// or the deadline of the options passed.
+/// Returns the flow, the used edges and whether the deadline passed.
+pub(super) fn max_flow(
+    source: &Address,
+    sink: &Address,
+    adjacencies: &mut Adjacencies,
+    max_distance: Option<u64>,
+    limit: U256,
+) -> (U256, HashMap<Node, HashMap<Node, U256>>, bool) {
+    let mut used_edges: HashMap<Node, HashMap<Node, U256>> = HashMap::new();
+
+    let mut flow = U256::default();
+    while flow < limit {
+        if adjacencies.deadline_passed() {
+            return (flow, used_edges, true);
+        }
+        let (new_flow, parents) = augmenting_path(source, sink, adjacencies, max_distance);
+        if new_flow == U256::default() {
+            break;
+        }
+        flow += new_flow;
+        for window in parents.windows(2) {
+            if let [node, prev] = window {
+                adjacencies.adjust_capacity(prev, node, -new_flow);
+                adjacencies.adjust_capacity(node, prev, new_flow);
+                if adjacencies.is_adjacent(node, prev) {
+                    *used_edges
+                        .entry(node.clone())
+                        .or_default()
+                        .entry(prev.clone())
+                        .or_default() -= new_flow;
+                } else {
+                    *used_edges
+                        .entry(prev.clone())
+                        .or_default()
+                        .entry(node.clone())
+                        .or_default() += new_flow;
+                }
+            } else {
+                panic!();
+            }
+        }
+    }
+    (flow, used_edges, false)
+}
+
 fn augmenting_path(
     source: &Address,
     sink: &Address,
@@ -229,6 +531,37 @@ fn prune_flow(
     flow_to_prune
 }
 
+/// Prunes the flow out of the sender's balances, starting with the
+/// tokens of lowest weight, but keeps the tokens of highest weight.
+/// Returns the remaining flow to prune.
+fn prune_by_token_weight(
+    source: &Address,
+    mut flow_to_prune: U256,
+    used_edges: &mut HashMap<Node, HashMap<Node, U256>>,
+    options: &FlowOptions,
+) -> U256 {
+    let source = Node::Node(*source);
+    let weight = |node: &Node| match node {
+        Node::BalanceNode(_, token) => options.token_weight(token),
+        _ => 0,
+    };
+    let mut balance_nodes = used_edges
+        .get(&source)
+        .map(|out| out.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    balance_nodes.sort_by_key(|node| (weight(node), node.clone()));
+    let max_weight = balance_nodes.iter().map(weight).max();
+    for node in balance_nodes {
+        if Some(weight(&node)) == max_weight || flow_to_prune == U256::from(0) {
+            break;
+        }
+        if used_edges[&source].contains_key(&node) {
+            flow_to_prune = prune_edge(used_edges, (&source, &node), flow_to_prune);
+        }
+    }
+    flow_to_prune
+}
+
 fn reduce_transfers(
     max_transfers: u64,
     used_edges: &mut HashMap<Node, HashMap<Node, U256>>,
@@ -428,6 +761,25 @@ fn prune_path(
     }
 }
 
+/// Replaces mint nodes by the node of the group, since the
+/// group receives the collateral and sends the minted tokens.
+fn merge_mint_nodes(
+    used_edges: HashMap<Node, HashMap<Node, U256>>,
+) -> HashMap<Node, HashMap<Node, U256>> {
+    let as_account = |node: Node| match node {
+        Node::MintNode(group) => Node::Node(group),
+        node => node,
+    };
+    let mut merged: HashMap<Node, HashMap<Node, U256>> = HashMap::new();
+    for (node, out) in used_edges {
+        let merged_out = merged.entry(as_account(node)).or_default();
+        for (target, capacity) in out {
+            *merged_out.entry(as_account(target)).or_default() += capacity;
+        }
+    }
+    merged
+}
+
 fn extract_transfers(
     source: &Address,
     sink: &Address,
@@ -492,7 +844,7 @@ fn next_full_capacity_edge(
     panic!();
 }
 
-fn find_pair_to_simplify(transfers: &Vec<Edge>) -> Option<(usize, usize)> {
+fn find_pair_to_simplify(transfers: &[Edge]) -> Option<(usize, usize)> {
     let l = transfers.len();
     (0..l)
         .flat_map(move |x| (0..l).map(move |y| (x, y)))
@@ -505,12 +857,16 @@ fn find_pair_to_simplify(transfers: &Vec<Edge>) -> Option<(usize, usize)> {
         })
 }
 
-fn simplify_transfers(mut transfers: Vec<Edge>) -> Vec<Edge> {
+fn simplify_transfers(mut transfers: Vec<Edge>, options: &FlowOptions) -> Vec<Edge> {
     // We can simplify the transfers:
     // If we have a transfer (A, B, T) and a transfer (B, C, T),
     // We can always replace both by (A, C, T).
+    // This only saves transfers, so it stops at the deadline.
 
-    while let Some((i, j)) = find_pair_to_simplify(&transfers) {
+    while !options.deadline_passed() {
+        let Some((i, j)) = find_pair_to_simplify(&transfers) else {
+            break;
+        };
         transfers[i].to = transfers[j].to;
         transfers.remove(j);
     }
@@ -543,20 +899,8 @@ fn sort_transfers(transfers: Vec<Edge>) -> Vec<Edge> {
 #[cfg(test)]
 mod test {
     use super::*;
-
-    fn addresses() -> (Address, Address, Address, Address, Address, Address) {
-        (
-            Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E"),
-            Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37"),
-            Address::from("0x33cEDde51198D1773590311E2A340DC06B24cB37"),
-            Address::from("0x447EDde51198D1773590311E2A340DC06B24cB37"),
-            Address::from("0x55c16ce62d26fd51582a646e2e30a3267b1e6d7e"),
-            Address::from("0x66c16ce62d26fd51582a646e2e30a3267b1e6d7e"),
-        )
-    }
-    fn build_edges(input: Vec<Edge>) -> EdgeDB {
-        EdgeDB::new(input)
-    }
+    use crate::graph::test_util::{addresses, build_edges};
+    use crate::types::edge::GraphModel;
 
     #[test]
     fn direct() {
@@ -709,6 +1053,229 @@ mod test {
         );
     }
 
+    #[test]
+    fn max_flow_only() {
+        let (a, b, c, d, t1, t2) = addresses();
+        let edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: t1,
+                capacity: U256::from(10),
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: t2,
+                capacity: U256::from(7),
+            },
+            Edge {
+                from: b,
+                to: d,
+                token: t2,
+                capacity: U256::from(9),
+            },
+            Edge {
+                from: c,
+                to: d,
+                token: t1,
+                capacity: U256::from(8),
+            },
+        ]);
+        let max_flow = |requested: U256, max_distance: Option<u64>| {
+            compute_max_flow(
+                &a,
+                &d,
+                &edges,
+                requested,
+                max_distance,
+                &FlowOptions::default(),
+            )
+            .0
+        };
+        assert_eq!(max_flow(U256::MAX, None), U256::from(16));
+        assert_eq!(max_flow(U256::from(6), None), U256::from(6));
+        assert_eq!(max_flow(U256::from(10), None), U256::from(10));
+        assert_eq!(max_flow(U256::MAX, Some(1)), U256::from(0));
+        let options = FlowOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        assert_eq!(
+            compute_max_flow(&a, &d, &edges, U256::MAX, None, &options),
+            (U256::from(0), true)
+        );
+    }
+
+    #[test]
+    fn deadline() {
+        let (a, b, c, ..) = addresses();
+        let edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: a,
+                capacity: U256::from(10),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: b,
+                capacity: U256::from(8),
+            },
+        ]);
+        let network = FlowNetwork::new(&edges);
+        let options = FlowOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        let max_flow = network.max_flow(&a, &c, &edges, None, &options);
+        assert!(max_flow.timed_out());
+        assert_eq!(max_flow.flow(), U256::from(0));
+        let max_flow = network.max_flow(&a, &c, &edges, None, &FlowOptions::default());
+        assert!(!max_flow.timed_out());
+        assert_eq!(max_flow.flow(), U256::from(8));
+    }
+
+    #[test]
+    fn simplify_until_deadline() {
+        let (a, b, c, _, t, _) = addresses();
+        let transfers = vec![
+            Edge {
+                from: a,
+                to: b,
+                token: t,
+                capacity: U256::from(5),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: t,
+                capacity: U256::from(5),
+            },
+        ];
+        let simplified = simplify_transfers(transfers.clone(), &FlowOptions::default());
+        assert_eq!(
+            simplified,
+            vec![Edge {
+                from: a,
+                to: c,
+                token: t,
+                capacity: U256::from(5),
+            }]
+        );
+        let options = FlowOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        assert_eq!(simplify_transfers(transfers.clone(), &options), transfers);
+    }
+
+    #[test]
+    fn shared_network() {
+        let (a, b, c, d, t1, t2) = addresses();
+        let mut edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: t1,
+                capacity: U256::from(10),
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: t2,
+                capacity: U256::from(7),
+            },
+            Edge {
+                from: b,
+                to: d,
+                token: t2,
+                capacity: U256::from(9),
+            },
+            Edge {
+                from: c,
+                to: d,
+                token: t1,
+                capacity: U256::from(8),
+            },
+        ]);
+        edges.update_balance(a, t1, U256::from(6));
+        let network = FlowNetwork::new(&edges);
+        let set = |addresses: &[Address]| addresses.iter().copied().collect();
+        for options in [
+            FlowOptions::default(),
+            FlowOptions {
+                exclude_addresses: set(&[c]),
+                ..Default::default()
+            },
+            FlowOptions {
+                accepted_tokens: Some(set(&[t1])),
+                ..Default::default()
+            },
+        ] {
+            // The maximum flow does not depend on the requested amount.
+            let max_flow = network.max_flow(&a, &d, &edges, None, &options);
+            for requested in [U256::MAX, U256::from(5)] {
+                let mut expected =
+                    compute_flow_with_options(&a, &d, &edges, requested, None, None, &options);
+                let mut flow =
+                    network.compute_flow(&a, &d, &edges, requested, None, None, &options);
+                expected.1.sort();
+                flow.1.sort();
+                assert_eq!(flow, expected);
+                assert_eq!(
+                    network.compute_max_flow(&a, &d, &edges, requested, None, &options),
+                    (expected.0, false)
+                );
+                let mut reused =
+                    transfers_from_max_flow(&a, &d, max_flow.clone(), requested, None, &options);
+                reused.1.sort();
+                assert_eq!(reused, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn update_network() {
+        let (a, b, c, d, t1, t2) = addresses();
+        let mut edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: t1,
+                capacity: U256::from(10),
+            },
+            Edge {
+                from: b,
+                to: d,
+                token: t2,
+                capacity: U256::from(9),
+            },
+        ]);
+        let mut network = FlowNetwork::new(&edges);
+        let changed_edges = [
+            Edge {
+                from: a,
+                to: b,
+                token: t1,
+                capacity: U256::from(0),
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: t2,
+                capacity: U256::from(7),
+            },
+        ];
+        for edge in changed_edges {
+            edges.update(edge);
+        }
+        edges.update_balance(b, t2, U256::from(3));
+        network.update(&edges, &changed_edges, &[(b, t2)]);
+        assert_eq!(network, FlowNetwork::new(&edges));
+    }
+
     #[test]
     fn trust_transfer_limit() {
         let (a, b, c, d, ..) = addresses();
@@ -748,4 +1315,241 @@ mod test {
         println!("{:?}", &flow.1);
         assert_eq!(flow.0, U256::from(9));
     }
+
+    #[test]
+    fn balance_feeds_several_edges() {
+        let (a, b, c, d, ..) = addresses();
+        let mut edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: a,
+                capacity: U256::from(10),
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: a,
+                capacity: U256::from(11),
+            },
+            Edge {
+                from: b,
+                to: d,
+                token: b,
+                capacity: U256::from(100),
+            },
+            Edge {
+                from: c,
+                to: d,
+                token: c,
+                capacity: U256::from(100),
+            },
+        ]);
+        // Without balances, the balance is approximated by the larger send limit.
+        let flow = compute_flow(&a, &d, &edges, U256::MAX, None, None);
+        assert_eq!(flow.0, U256::from(11));
+        edges.update_balance(a, a, U256::from(15));
+        let flow = compute_flow(&a, &d, &edges, U256::MAX, None, None);
+        assert_eq!(flow.0, U256::from(15));
+        edges.update_balance(a, a, U256::from(30));
+        let flow = compute_flow(&a, &d, &edges, U256::MAX, None, None);
+        assert_eq!(flow.0, U256::from(21));
+    }
+
+    #[test]
+    fn mint_capacity() {
+        let (a, b, c, ..) = addresses();
+        // b is a group that mints at most 4 group tokens from a's tokens.
+        let mut edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: a,
+                capacity: U256::from(10),
+            },
+            Edge {
+                from: b,
+                to: b,
+                token: a,
+                capacity: U256::from(4),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: b,
+                capacity: U256::MAX,
+            },
+        ]);
+        edges.update_balance(b, b, U256::from(0));
+        // In v1, there are no groups and b cannot send tokens it does not hold.
+        let flow = compute_flow(&a, &c, &edges, U256::MAX, None, None);
+        assert_eq!(flow.0, U256::from(0));
+        edges.set_model(GraphModel::V2);
+        let flow = compute_flow(&a, &c, &edges, U256::MAX, None, None);
+        assert_eq!(flow.0, U256::from(4));
+        // Sending to the group itself is not limited by minting.
+        let flow = compute_flow(&a, &b, &edges, U256::MAX, None, None);
+        assert_eq!(flow.0, U256::from(10));
+    }
+
+    #[test]
+    fn filters() {
+        let (a, b, c, d, ..) = addresses();
+        let mut edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: a,
+                capacity: U256::from(10),
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: a,
+                capacity: U256::from(11),
+            },
+            Edge {
+                from: b,
+                to: d,
+                token: b,
+                capacity: U256::from(100),
+            },
+            Edge {
+                from: c,
+                to: d,
+                token: c,
+                capacity: U256::from(100),
+            },
+        ]);
+        edges.update_balance(a, a, U256::from(30));
+        let flow_with = |options: FlowOptions| {
+            compute_flow_with_options(&a, &d, &edges, U256::MAX, None, None, &options).0
+        };
+        assert_eq!(flow_with(FlowOptions::default()), U256::from(21));
+        let only = |addresses: &[Address]| Some(addresses.iter().copied().collect());
+        let set = |addresses: &[Address]| addresses.iter().copied().collect();
+        assert_eq!(
+            flow_with(FlowOptions {
+                exclude_addresses: set(&[b]),
+                ..Default::default()
+            }),
+            U256::from(11)
+        );
+        assert_eq!(
+            flow_with(FlowOptions {
+                exclude_tokens: set(&[c]),
+                ..Default::default()
+            }),
+            U256::from(10)
+        );
+        assert_eq!(
+            flow_with(FlowOptions {
+                only_tokens: only(&[a, b]),
+                ..Default::default()
+            }),
+            U256::from(10)
+        );
+        assert_eq!(
+            flow_with(FlowOptions {
+                only_intermediaries: only(&[c]),
+                ..Default::default()
+            }),
+            U256::from(11)
+        );
+        // Only restricts the tokens arriving at the sink.
+        assert_eq!(
+            flow_with(FlowOptions {
+                accepted_tokens: only(&[c]),
+                ..Default::default()
+            }),
+            U256::from(11)
+        );
+        assert_eq!(
+            flow_with(FlowOptions {
+                accepted_tokens: only(&[a]),
+                ..Default::default()
+            }),
+            U256::from(0)
+        );
+        // The source and the sink are not intermediaries.
+        assert_eq!(
+            flow_with(FlowOptions {
+                exclude_addresses: set(&[a, d]),
+                only_intermediaries: only(&[]),
+                ..Default::default()
+            }),
+            U256::from(0)
+        );
+        assert_eq!(
+            flow_with(FlowOptions {
+                exclude_addresses: set(&[a, d]),
+                ..Default::default()
+            }),
+            U256::from(21)
+        );
+    }
+
+    #[test]
+    fn token_contract_addresses() {
+        let (a, b, _, d, e, ..) = addresses();
+        let edges = EdgeDB::new_with_token_addresses(vec![], HashMap::from([(a, d)]));
+        let mut options = FlowOptions {
+            exclude_tokens: HashSet::from([d]),
+            only_tokens: Some(HashSet::from([b, d])),
+            accepted_tokens: Some(HashSet::from([e])),
+            token_weights: HashMap::from([(d, 2), (b, 1)]),
+            ..Default::default()
+        };
+        options.resolve_token_addresses(&edges);
+        assert_eq!(options.token_weight(&a), 2);
+        assert_eq!(options.token_weight(&b), 1);
+        assert_eq!(options.token_weight(&d), 0);
+        assert_eq!(options.exclude_tokens, HashSet::from([a]));
+        assert_eq!(options.only_tokens, Some(HashSet::from([a, b])));
+        // Unknown addresses are kept.
+        assert_eq!(options.accepted_tokens, Some(HashSet::from([e])));
+    }
+
+    #[test]
+    fn token_weights() {
+        let (a, b, c, d, ..) = addresses();
+        // a holds its own tokens and b's and c's tokens, d accepts all of them.
+        let edges = build_edges(
+            [a, b, c]
+                .into_iter()
+                .map(|token| Edge {
+                    from: a,
+                    to: d,
+                    token,
+                    capacity: U256::from(10),
+                })
+                .collect(),
+        );
+        let spent_tokens = |weights: &[(Address, i64)], amount: u128| {
+            let options = FlowOptions {
+                token_weights: weights.iter().copied().collect(),
+                ..Default::default()
+            };
+            let (flow, transfers) =
+                compute_flow_with_options(&a, &d, &edges, U256::from(amount), None, None, &options);
+            assert_eq!(flow, U256::from(amount));
+            let mut spent = transfers
+                .iter()
+                .map(|t| (t.token, t.capacity))
+                .collect::<Vec<_>>();
+            spent.sort();
+            spent
+        };
+        assert_eq!(spent_tokens(&[(b, 1)], 10), vec![(b, U256::from(10))]);
+        assert_eq!(spent_tokens(&[(c, 1)], 10), vec![(c, U256::from(10))]);
+        // Spend others' tokens before the own tokens.
+        assert_eq!(
+            spent_tokens(&[(a, -1), (b, 1)], 15),
+            vec![(b, U256::from(10)), (c, U256::from(5))]
+        );
+        assert_eq!(
+            spent_tokens(&[(a, -1)], 25),
+            vec![(a, U256::from(5)), (b, U256::from(10)), (c, U256::from(10))]
+        );
+    }
 }
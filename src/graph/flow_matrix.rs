@@ -0,0 +1,190 @@
+use std::collections::BTreeSet;
+
+use json::JsonValue;
+
+use crate::types::{Address, Edge, U256};
+
+/// The arguments of `operateFlowMatrix` of the Circles v2 Hub
+/// for a transfer from a single source to a single sink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowMatrix {
+    /// All addresses involved, i.e. senders, receivers and tokens,
+    /// sorted in ascending order as required by the Hub.
+    pub flow_vertices: Vec<Address>,
+    pub flow_edges: Vec<FlowEdge>,
+    pub streams: Vec<Stream>,
+    /// For each flow edge, the indices of the token, the sender and
+    /// the receiver in `flow_vertices`, each as big-endian u16.
+    pub packed_coordinates: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowEdge {
+    /// The one-based id of the stream if the edge ends at its sink, zero otherwise.
+    pub stream_sink_id: u16,
+    pub amount: U256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stream {
+    pub source_coordinate: u16,
+    /// The flow edges ending at the sink of the stream.
+    pub flow_edge_ids: Vec<u16>,
+    pub data: Vec<u8>,
+}
+
+/// Flow edge amounts are `uint192`.
+const MAX_AMOUNT: U256 = U256::new(u128::MAX >> 64, u128::MAX);
+
+/// Encodes the transfers computed by `compute_flow` on a v2 graph,
+/// where the token of an edge is the avatar that issued it.
+pub fn flow_matrix(
+    source: &Address,
+    sink: &Address,
+    transfers: &[Edge],
+) -> Result<FlowMatrix, String> {
+    let mut vertices = BTreeSet::from([*source, *sink]);
+    for edge in transfers {
+        vertices.extend([edge.from, edge.to, edge.token]);
+    }
+    let flow_vertices = vertices.into_iter().collect::<Vec<_>>();
+    if flow_vertices.len() > u16::MAX as usize {
+        return Err(format!("Too many flow vertices: {}", flow_vertices.len()));
+    }
+    let coordinate = |address: &Address| flow_vertices.binary_search(address).unwrap() as u16;
+
+    let mut flow_edges = vec![];
+    let mut packed_coordinates = vec![];
+    let mut sink_edge_ids = vec![];
+    for (i, edge) in transfers.iter().enumerate() {
+        if edge.capacity > MAX_AMOUNT {
+            return Err(format!("Amount too large: {}", edge.capacity));
+        }
+        let ends_at_sink = edge.to == *sink;
+        if ends_at_sink {
+            sink_edge_ids.push(i as u16);
+        }
+        flow_edges.push(FlowEdge {
+            stream_sink_id: u16::from(ends_at_sink),
+            amount: edge.capacity,
+        });
+        for address in [&edge.token, &edge.from, &edge.to] {
+            packed_coordinates.extend(coordinate(address).to_be_bytes());
+        }
+    }
+    let streams = vec![Stream {
+        source_coordinate: coordinate(source),
+        flow_edge_ids: sink_edge_ids,
+        data: vec![],
+    }];
+    Ok(FlowMatrix {
+        flow_vertices,
+        flow_edges,
+        streams,
+        packed_coordinates,
+    })
+}
+
+impl FlowMatrix {
+    pub fn to_json(&self) -> JsonValue {
+        json::object! {
+            flowVertices: self.flow_vertices.iter().map(|v| v.to_checksummed_hex()).collect::<Vec<_>>(),
+            flowEdges: self.flow_edges.iter().map(|e| json::object! {
+                streamSinkId: e.stream_sink_id,
+                amount: e.amount.to_decimal(),
+            }).collect::<Vec<_>>(),
+            streams: self.streams.iter().map(|s| json::object! {
+                sourceCoordinate: s.source_coordinate,
+                flowEdgeIds: s.flow_edge_ids.clone(),
+                data: to_hex(&s.data),
+            }).collect::<Vec<_>>(),
+            packedCoordinates: to_hex(&self.packed_coordinates),
+        }
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    let mut hex = String::from("0x");
+    for byte in data {
+        hex += &format!("{byte:02x}");
+    }
+    hex
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let (a, b, c) = (
+            Address::from([3; 20]),
+            Address::from([1; 20]),
+            Address::from([2; 20]),
+        );
+        // a sends its tokens to b, b sends a's and its own tokens to c.
+        let transfers = [
+            Edge {
+                from: a,
+                to: b,
+                token: a,
+                capacity: U256::from(5),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: a,
+                capacity: U256::from(5),
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: a,
+                capacity: U256::from(2),
+            },
+        ];
+        let matrix = flow_matrix(&a, &c, &transfers).unwrap();
+        assert_eq!(matrix.flow_vertices, vec![b, c, a]);
+        assert_eq!(
+            matrix
+                .flow_edges
+                .iter()
+                .map(|e| e.stream_sink_id)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 1]
+        );
+        assert_eq!(
+            matrix.streams,
+            vec![Stream {
+                source_coordinate: 2,
+                flow_edge_ids: vec![1, 2],
+                data: vec![]
+            }]
+        );
+        assert_eq!(
+            to_hex(&matrix.packed_coordinates),
+            "0x000200020000000200000001000200020001"
+        );
+        assert_eq!(
+            matrix.to_json()["packedCoordinates"],
+            "0x000200020000000200000001000200020001"
+        );
+    }
+
+    #[test]
+    fn amount_too_large() {
+        let (a, b) = (Address::from([1; 20]), Address::from([2; 20]));
+        let edge = Edge {
+            from: a,
+            to: b,
+            token: a,
+            capacity: MAX_AMOUNT,
+        };
+        assert!(flow_matrix(&a, &b, &[edge]).is_ok());
+        let edge = Edge {
+            capacity: MAX_AMOUNT + U256::from(1),
+            ..edge
+        };
+        assert!(flow_matrix(&a, &b, &[edge]).is_err());
+    }
+}
@@ -2,7 +2,13 @@ use crate::types::Address;
 use std::fmt::{Display, Formatter};
 
 mod adjacencies;
+pub mod diagnosis;
 mod flow;
+pub mod flow_matrix;
+pub mod reachability;
+pub mod suggestions;
+#[cfg(test)]
+mod test_util;
 
 // An edge from the capacity network is
 // from, token, to -> capacity
@@ -13,16 +19,30 @@ mod flow;
 // from -A-> BalanceNode(from, token) -B-> TrustNode(to, token) -C-> to
 //
 // The capacities (A, B, C) are as follows:
-// A: the max of all capacity-netwok edges of the form (from, token, *), or A's balance of "token" tokens.
+// A: A's balance of "token" tokens, or, if it is not known, the max of all capacity-netwok edges of the form (from, token, *).
 // B: the actual capacity of the capacity-network edge (from, token, to), or the "send limit" from "from" to "to" in "token" tokens
 // C: if "token" is C's token (this is a "send to owner" edge): infinity or the sum of all incoming edges.
 //    otherwise: the max of all capacity-network edges of the form (*, token, to) or the trust limit of "to" for "token" tokens.
+//    In the v2 model, trust is not limited, so C is always the sum of all incoming edges.
+//
+// Groups (v2) mint their own tokens from collateral tokens they accept. This is expressed
+// by a "mint edge" (group, collateral, group) in the capacity network, whose capacity is
+// the amount of group tokens that can be minted from that collateral. It is transformed into
+//
+// TrustNode(group, collateral) -D-> MintNode(group) -E-> BalanceNode(group, group)
+//
+// D: the capacity of the mint edge
+// E: infinity
+//
+// so that collateral sent to the group can leave it as group tokens. In the transfers,
+// the mint node is the account of the group.
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone, PartialOrd, Ord)]
 pub enum Node {
     Node(Address),
     BalanceNode(Address, Address),
     TrustNode(Address, Address),
+    MintNode(Address),
 }
 
 pub fn node_as_address(node: &Node) -> &Address {
@@ -47,9 +67,16 @@ impl Display for Node {
             Node::Node(address) => write!(f, "{address}"),
             Node::BalanceNode(from, token) => write!(f, "(bal {from} x {token})"),
             Node::TrustNode(to, token) => write!(f, "(trust {to} x {token})"),
+            Node::MintNode(group) => write!(f, "(mint {group})"),
         }
     }
 }
 
+pub use crate::graph::adjacencies::FlowNetwork;
 pub use crate::graph::flow::compute_flow;
+pub use crate::graph::flow::compute_flow_with_options;
+pub use crate::graph::flow::compute_max_flow;
+pub use crate::graph::flow::transfers_from_max_flow;
 pub use crate::graph::flow::transfers_to_dot;
+pub use crate::graph::flow::FlowOptions;
+pub use crate::graph::flow::MaxFlow;
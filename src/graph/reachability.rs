@@ -0,0 +1,414 @@
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::types::demurrage::Demurrage;
+use crate::types::edge::{EdgeDB, GraphModel};
+use crate::types::{Address, Edge, U256};
+
+/// An index of an edge database that answers whether any amount can be sent
+/// from one address to another and bounds that amount, without computing a flow.
+/// It ignores the filters of `FlowOptions`, which can only reduce the flow.
+/// The amounts refer to the timestamp of the edges, `max_flow_upper_bound_as_of`
+/// converts them to another point in time.
+#[derive(Debug, Default, Clone)]
+pub struct ReachabilityIndex {
+    /// The strongly connected component of each address over the edges
+    /// with positive capacity, numbered in reverse topological order.
+    components: HashMap<Address, usize>,
+    /// The components directly reachable from each component.
+    successors: Vec<Vec<usize>>,
+    /// The total balance each address can send.
+    outgoing_capacity: HashMap<Address, U256>,
+    /// The total amount each address can receive.
+    incoming_capacity: HashMap<Address, U256>,
+    /// The timestamp of the edges, if their amounts are demurraged.
+    timestamp: Option<u64>,
+}
+
+impl ReachabilityIndex {
+    pub fn new(edges: &EdgeDB) -> ReachabilityIndex {
+        let minting_groups = edges
+            .edges()
+            .iter()
+            .filter(|e| edges.is_mint(e) && e.capacity != U256::from(0))
+            .map(|e| e.from)
+            .collect::<HashSet<_>>();
+        let usable = |e: &Edge| is_usable(edges, e, |group| minting_groups.contains(group));
+
+        let mut addresses = vec![];
+        let mut ids = HashMap::new();
+        let mut adjacencies: Vec<Vec<usize>> = vec![];
+        let mut id = |address: Address, adjacencies: &mut Vec<Vec<usize>>| {
+            *ids.entry(address).or_insert_with(|| {
+                addresses.push(address);
+                adjacencies.push(vec![]);
+                addresses.len() - 1
+            })
+        };
+        for edge in edges.edges().iter().filter(|e| usable(e)) {
+            let (from, to) = (
+                id(edge.from, &mut adjacencies),
+                id(edge.to, &mut adjacencies),
+            );
+            adjacencies[from].push(to);
+        }
+
+        let mut outgoing_capacity = HashMap::new();
+        let mut incoming_capacity = HashMap::new();
+        for address in &addresses {
+            let sent = outgoing_capacity_of(edges, address, usable);
+            if sent != U256::from(0) {
+                outgoing_capacity.insert(*address, sent);
+            }
+            let received = incoming_capacity_of(edges, address, usable);
+            if received != U256::from(0) {
+                incoming_capacity.insert(*address, received);
+            }
+        }
+
+        let component_of = strongly_connected_components(&adjacencies);
+        let component_count = component_of.iter().map(|c| c + 1).max().unwrap_or(0);
+        let mut successors = vec![HashSet::new(); component_count];
+        for (from, targets) in adjacencies.iter().enumerate() {
+            for to in targets {
+                if component_of[from] != component_of[*to] {
+                    successors[component_of[from]].insert(component_of[*to]);
+                }
+            }
+        }
+        ReachabilityIndex {
+            components: addresses.into_iter().zip(component_of).collect(),
+            successors: successors
+                .into_iter()
+                .map(|s| s.into_iter().collect())
+                .collect(),
+            outgoing_capacity,
+            incoming_capacity,
+            timestamp: edges.timestamp(),
+        }
+    }
+
+    /// @returns false if nothing can be sent from `source` to `sink`.
+    pub fn is_reachable(&self, source: &Address, sink: &Address) -> bool {
+        if source == sink {
+            return true;
+        }
+        let (Some(&source), Some(&sink)) = (self.components.get(source), self.components.get(sink))
+        else {
+            return false;
+        };
+        // Components only reach components with lower numbers.
+        let mut visited = HashSet::from([source]);
+        let mut queue = VecDeque::from([source]);
+        while let Some(component) = queue.pop_front() {
+            if component == sink {
+                return true;
+            }
+            for next in &self.successors[component] {
+                if *next >= sink && visited.insert(*next) {
+                    queue.push_back(*next);
+                }
+            }
+        }
+        false
+    }
+
+    /// An upper bound of the maximum flow from `source` to `sink`:
+    /// Zero if the sink is not reachable, otherwise the minimum of the total
+    /// balance of the source and the total amount the sink can receive.
+    pub fn max_flow_upper_bound(&self, source: &Address, sink: &Address) -> U256 {
+        if source == sink || !self.is_reachable(source, sink) {
+            return U256::from(0);
+        }
+        min(
+            self.outgoing_capacity
+                .get(source)
+                .copied()
+                .unwrap_or_default(),
+            self.incoming_capacity
+                .get(sink)
+                .copied()
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Updates the index from `old` to `edges`, where only the edges of the senders
+    /// of `changed_edges` and the holders of `changed_balances` were changed.
+    /// Keeps the components if the receivers the changed senders can send to are
+    /// the same as before, and rebuilds the index otherwise.
+    pub fn updated(
+        &self,
+        old: &EdgeDB,
+        edges: &EdgeDB,
+        changed_edges: &[Edge],
+        changed_balances: &[(Address, Address)],
+    ) -> ReachabilityIndex {
+        let senders = changed_edges
+            .iter()
+            .map(|e| e.from)
+            .chain(changed_balances.iter().map(|(holder, _)| *holder))
+            .collect::<HashSet<_>>();
+        let mut receivers = HashSet::new();
+        for sender in &senders {
+            let targets = receivers_of(edges, sender);
+            if targets != receivers_of(old, sender) {
+                return ReachabilityIndex::new(edges);
+            }
+            receivers.extend(targets);
+        }
+        let usable = |e: &Edge| is_usable(edges, e, |group| can_mint(edges, group));
+        let mut index = self.clone();
+        for sender in &senders {
+            index
+                .outgoing_capacity
+                .insert(*sender, outgoing_capacity_of(edges, sender, usable));
+        }
+        for receiver in &receivers {
+            index
+                .incoming_capacity
+                .insert(*receiver, incoming_capacity_of(edges, receiver, usable));
+        }
+        index.timestamp = edges.timestamp();
+        index
+    }
+
+    /// Like `max_flow_upper_bound`, for a flow computed as of `as_of`: Demurrage
+    /// increases the amounts before the timestamp of the edges and decreases them after.
+    pub fn max_flow_upper_bound_as_of(
+        &self,
+        source: &Address,
+        sink: &Address,
+        as_of: Option<u64>,
+    ) -> U256 {
+        let bound = self.max_flow_upper_bound(source, sink);
+        match (self.timestamp, as_of) {
+            (Some(timestamp), Some(as_of)) => Demurrage::between(timestamp, as_of).apply(bound),
+            _ => bound,
+        }
+    }
+}
+
+/// An edge can be used if its sender holds the token or, if it is a group,
+/// can mint it.
+fn is_usable(edges: &EdgeDB, edge: &Edge, can_mint: impl Fn(&Address) -> bool) -> bool {
+    edge.capacity != U256::from(0)
+        && !edges.is_mint(edge)
+        && (edges.balance(&edge.from, &edge.token) != Some(U256::from(0))
+            || (edge.token == edge.from && can_mint(&edge.from)))
+}
+
+fn can_mint(edges: &EdgeDB, group: &Address) -> bool {
+    edges.outgoing(group).iter().any(|e| edges.is_mint(e))
+}
+
+/// The receivers of the usable edges of `sender`.
+fn receivers_of(edges: &EdgeDB, sender: &Address) -> HashSet<Address> {
+    edges
+        .outgoing(sender)
+        .into_iter()
+        .filter(|e| is_usable(edges, e, |group| can_mint(edges, group)))
+        .map(|e| e.to)
+        .collect()
+}
+
+/// The total balance `holder` can send over its usable edges.
+fn outgoing_capacity_of(edges: &EdgeDB, holder: &Address, usable: impl Fn(&Edge) -> bool) -> U256 {
+    let mut sent: HashMap<Address, U256> = HashMap::new();
+    for edge in edges.outgoing(holder).into_iter().filter(|e| usable(e)) {
+        let capacity = sent.entry(edge.token).or_default();
+        *capacity = max(*capacity, edge.capacity);
+    }
+    sent.into_iter()
+        .map(|(token, capacity)| edges.balance(holder, &token).unwrap_or(capacity))
+        .fold(U256::from(0), U256::saturating_add)
+}
+
+/// The total amount `receiver` can receive over its usable edges.
+fn incoming_capacity_of(
+    edges: &EdgeDB,
+    receiver: &Address,
+    usable: impl Fn(&Edge) -> bool,
+) -> U256 {
+    let mut received: HashMap<Address, U256> = HashMap::new();
+    for edge in edges.incoming(receiver).into_iter().filter(|e| usable(e)) {
+        let capacity = received.entry(edge.token).or_default();
+        if edge.token == edge.to || edges.model() == GraphModel::V2 {
+            *capacity = capacity.saturating_add(edge.capacity);
+        } else {
+            *capacity = max(*capacity, edge.capacity);
+        }
+    }
+    received
+        .into_values()
+        .fold(U256::from(0), U256::saturating_add)
+}
+
+/// Tarjan's algorithm, without recursion. Returns the component of each node,
+/// numbered in reverse topological order.
+fn strongly_connected_components(adjacencies: &[Vec<usize>]) -> Vec<usize> {
+    const UNVISITED: usize = usize::MAX;
+    let n = adjacencies.len();
+    let mut index = vec![UNVISITED; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = vec![];
+    let mut component = vec![UNVISITED; n];
+    let mut next_index = 0;
+    let mut component_count = 0;
+    for root in 0..n {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        let mut call_stack = vec![(root, 0)];
+        while let Some((v, i)) = call_stack.pop() {
+            if i == 0 {
+                index[v] = next_index;
+                low[v] = next_index;
+                next_index += 1;
+                stack.push(v);
+                on_stack[v] = true;
+            }
+            if i < adjacencies[v].len() {
+                call_stack.push((v, i + 1));
+                let w = adjacencies[v][i];
+                if index[w] == UNVISITED {
+                    call_stack.push((w, 0));
+                } else if on_stack[w] {
+                    low[v] = min(low[v], index[w]);
+                }
+            } else {
+                if low[v] == index[v] {
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        component[w] = component_count;
+                        if w == v {
+                            break;
+                        }
+                    }
+                    component_count += 1;
+                }
+                if let Some((parent, _)) = call_stack.last() {
+                    low[*parent] = min(low[*parent], low[v]);
+                }
+            }
+        }
+    }
+    component
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::test_util::{addresses, edge};
+    use crate::graph::{compute_max_flow, FlowOptions};
+
+    #[test]
+    fn reachable() {
+        let (a, b, c, d, e, _) = addresses();
+        // a and b form a cycle, which can send to c, which can send to d.
+        let mut edges = EdgeDB::new(vec![
+            edge(a, b, a, 10),
+            edge(b, a, b, 10),
+            edge(b, c, b, 5),
+            edge(c, d, c, 5),
+            edge(d, e, d, 5),
+        ]);
+        // d has no balance to send to e.
+        edges.update_balance(d, d, U256::from(0));
+        let index = ReachabilityIndex::new(&edges);
+        assert!(index.is_reachable(&a, &d));
+        assert!(index.is_reachable(&b, &a));
+        assert!(index.is_reachable(&c, &d));
+        assert!(!index.is_reachable(&d, &a));
+        assert!(!index.is_reachable(&c, &a));
+        assert!(!index.is_reachable(&a, &e));
+        assert!(!index.is_reachable(&e, &a));
+    }
+
+    #[test]
+    fn upper_bound() {
+        let (a, b, c, d, ..) = addresses();
+        let mut edges = EdgeDB::new(vec![
+            edge(a, b, a, 10),
+            edge(a, c, a, 20),
+            edge(b, d, b, 7),
+            edge(c, d, c, 8),
+        ]);
+        edges.update_balance(a, a, U256::from(25));
+        let index = ReachabilityIndex::new(&edges);
+        assert_eq!(index.max_flow_upper_bound(&a, &d), U256::from(15));
+        assert_eq!(index.max_flow_upper_bound(&a, &b), U256::from(10));
+        assert_eq!(index.max_flow_upper_bound(&a, &c), U256::from(20));
+        assert_eq!(index.max_flow_upper_bound(&d, &a), U256::from(0));
+    }
+
+    #[test]
+    fn update() {
+        let (a, b, c, d, e, _) = addresses();
+        let mut edges = EdgeDB::new(vec![edge(a, b, a, 10), edge(b, c, b, 5), edge(c, d, c, 8)]);
+        let mut index = ReachabilityIndex::new(&edges);
+        let addresses = [a, b, c, d, e];
+        let assert_same = |index: &ReachabilityIndex, edges: &EdgeDB| {
+            let expected = ReachabilityIndex::new(edges);
+            for from in &addresses {
+                for to in &addresses {
+                    assert_eq!(
+                        index.is_reachable(from, to),
+                        expected.is_reachable(from, to)
+                    );
+                    assert_eq!(
+                        index.max_flow_upper_bound(from, to),
+                        expected.max_flow_upper_bound(from, to)
+                    );
+                }
+            }
+        };
+        // Changes of capacities and balances keep the components.
+        let old = edges.clone();
+        let updates = [edge(a, b, a, 4), edge(a, b, b, 3)];
+        for update in updates {
+            edges.update(update);
+        }
+        edges.update_balance(c, c, U256::from(2));
+        index = index.updated(&old, &edges, &updates, &[(c, c)]);
+        assert_same(&index, &edges);
+        // New connections and removed ones change them.
+        let old = edges.clone();
+        let updates = [edge(d, e, d, 1), edge(b, c, b, 0)];
+        for update in updates {
+            edges.update(update);
+        }
+        index = index.updated(&old, &edges, &updates, &[]);
+        assert_same(&index, &edges);
+    }
+
+    #[test]
+    fn upper_bound_as_of() {
+        let (a, b, ..) = addresses();
+        let one = U256::from(1_000_000_000_000_000_000u128);
+        let mut edges = EdgeDB::new(vec![Edge {
+            from: a,
+            to: b,
+            token: a,
+            capacity: one,
+        }]);
+        edges.update_balance(a, a, one);
+        edges.set_model(GraphModel::V2);
+        let timestamp = 1700000000;
+        edges.set_timestamp(Some(timestamp));
+        let index = ReachabilityIndex::new(&edges);
+        let year = 365 * 24 * 60 * 60;
+        let options = FlowOptions {
+            as_of: Some(timestamp - year),
+            ..Default::default()
+        };
+        // The balance was higher a year before, and so is the flow.
+        let (flow, _) = compute_max_flow(&a, &b, &edges, U256::MAX, None, &options);
+        assert!(flow > one);
+        let bound = index.max_flow_upper_bound_as_of(&a, &b, options.as_of);
+        assert!(bound >= flow);
+        assert_eq!(index.max_flow_upper_bound_as_of(&a, &b, None), one);
+        assert!(index.max_flow_upper_bound_as_of(&a, &b, Some(timestamp + year)) < one);
+    }
+}
@@ -0,0 +1,232 @@
+use std::cmp::{max, Reverse};
+use std::collections::HashMap;
+
+use crate::graph::adjacencies::{Adjacencies, FlowNetwork};
+use crate::graph::diagnosis::{diagnose_flow, BottleneckKind};
+use crate::graph::flow::{max_flow, FlowOptions};
+use crate::graph::Node;
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, U256};
+
+/// The maximum number of trusts suggested.
+const MAX_SUGGESTIONS: usize = 5;
+/// The number of candidate trusts evaluated for each suggestion.
+const MAX_CANDIDATES: usize = 10;
+
+/// A trust of `truster` in `token` and the maximum flow after adding it
+/// (together with all previously suggested trusts).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedTrust {
+    pub truster: Address,
+    pub token: Address,
+    pub flow: U256,
+}
+
+/// Suggests trusts of the sink in new tokens that let `source` send
+/// `requested_flow` to `sink`. This is a greedy heuristic: It only considers
+/// the tokens the diagnosis reports as missing trusts of the sink and adds the
+/// one that increases the maximum flow the most until the requested flow is
+/// reached, so the result is small, but not necessarily the smallest possible.
+/// New trusts are assumed not to be limited.
+/// The result is empty if no trust is needed and ends with a flow below
+/// `requested_flow` if it cannot be reached. If the deadline of the options
+/// passes, returns the trusts found so far and true as the second value.
+pub fn suggest_trusts_greedily(
+    source: &Address,
+    sink: &Address,
+    edges: &EdgeDB,
+    network: &FlowNetwork,
+    requested_flow: U256,
+    options: &FlowOptions,
+) -> (Vec<SuggestedTrust>, bool) {
+    // The capacities of the suggested trusts, added to the flow network.
+    let mut trusts = HashMap::new();
+    let max_flow_with = |trusts: &HashMap<Node, HashMap<Node, U256>>| {
+        let mut adjacencies = Adjacencies::new(edges, source, sink, options)
+            .with_network(network)
+            .with_extra_capacities(trusts);
+        let (flow, _, timed_out) = max_flow(source, sink, &mut adjacencies, None, U256::MAX);
+        (flow, timed_out)
+    };
+    let (mut flow, timed_out) = max_flow_with(&trusts);
+    if timed_out || flow >= requested_flow {
+        return (vec![], timed_out);
+    }
+    let (_, bottlenecks, timed_out) = diagnose_flow(source, sink, edges, options);
+    if timed_out {
+        return (vec![], true);
+    }
+    let mut candidates = bottlenecks
+        .into_iter()
+        .filter(|b| b.kind == BottleneckKind::MissingTrust)
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|b| Reverse(b.amount));
+
+    let mut suggestions = vec![];
+    while flow < requested_flow && suggestions.len() < MAX_SUGGESTIONS {
+        let mut best: Option<(U256, usize)> = None;
+        for (i, candidate) in candidates.iter().enumerate().take(MAX_CANDIDATES) {
+            let mut with_trust = trusts.clone();
+            add_trust(
+                &mut with_trust,
+                edges,
+                source,
+                sink,
+                &candidate.token,
+                options,
+            );
+            let (new_flow, timed_out) = max_flow_with(&with_trust);
+            if timed_out {
+                return (suggestions, true);
+            }
+            if new_flow > best.map_or(flow, |(f, _)| f) {
+                best = Some((new_flow, i));
+            }
+        }
+        let Some((new_flow, i)) = best else {
+            break;
+        };
+        let token = candidates.remove(i).token;
+        add_trust(&mut trusts, edges, source, sink, &token, options);
+        flow = new_flow;
+        suggestions.push(SuggestedTrust {
+            truster: *sink,
+            token,
+            flow,
+        });
+    }
+    (suggestions, false)
+}
+
+/// Adds the capacities of `sink` accepting any amount of `token` from all its holders.
+fn add_trust(
+    capacities: &mut HashMap<Node, HashMap<Node, U256>>,
+    edges: &EdgeDB,
+    source: &Address,
+    sink: &Address,
+    token: &Address,
+    options: &FlowOptions,
+) {
+    let mut holders: HashMap<Address, U256> = HashMap::new();
+    for edge in edges.edges() {
+        if edge.token == *token && !edges.is_mint(edge) {
+            let capacity = holders.entry(edge.from).or_default();
+            *capacity = max(*capacity, edge.capacity);
+        }
+    }
+    for ((holder, token_owner), balance) in edges.balances() {
+        if token_owner == token {
+            holders.insert(*holder, *balance);
+        }
+    }
+    let trust_node = Node::TrustNode(*sink, *token);
+    for (holder, capacity) in holders {
+        let edge = Edge {
+            from: holder,
+            to: *sink,
+            token: *token,
+            capacity,
+        };
+        if holder == *sink || capacity == U256::from(0) || !options.allows(&edge, source, sink) {
+            continue;
+        }
+        let balance_node = Node::BalanceNode(holder, *token);
+        for (from, to) in [
+            (Node::Node(holder), balance_node.clone()),
+            (balance_node, trust_node.clone()),
+        ] {
+            let c = capacities.entry(from).or_default().entry(to).or_default();
+            *c = max(*c, capacity);
+        }
+        capacities
+            .entry(trust_node.clone())
+            .or_default()
+            .insert(Node::Node(*sink), U256::MAX);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::test_util::{addresses, edge};
+    use std::time::Instant;
+
+    #[test]
+    fn suggest() {
+        let (a, b, c, d, ..) = addresses();
+        // a can send 10 of its tokens to b and 20 to c, but d trusts neither.
+        let mut edges = EdgeDB::new(vec![edge(a, b, a, 10), edge(a, c, a, 20)]);
+        edges.update_balance(a, a, U256::from(30));
+        let network = FlowNetwork::new(&edges);
+        let suggest = |amount: u128| {
+            suggest_trusts_greedily(
+                &a,
+                &d,
+                &edges,
+                &network,
+                U256::from(amount),
+                &FlowOptions::default(),
+            )
+            .0
+        };
+        assert_eq!(
+            suggest(30),
+            vec![SuggestedTrust {
+                truster: d,
+                token: a,
+                flow: U256::from(30),
+            }]
+        );
+        assert_eq!(suggest(0), vec![]);
+        // No suggestions are evaluated after the deadline.
+        let options = FlowOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        assert_eq!(
+            suggest_trusts_greedily(&a, &d, &edges, &network, U256::from(30), &options),
+            (vec![], true)
+        );
+    }
+
+    #[test]
+    fn suggest_several() {
+        let (a, b, c, d, ..) = addresses();
+        // a holds 10 b tokens and 20 c tokens, which b and c accept.
+        let mut edges = EdgeDB::new(vec![edge(a, b, b, 10), edge(a, c, c, 20)]);
+        edges.update_balance(a, b, U256::from(10));
+        edges.update_balance(a, c, U256::from(20));
+        let network = FlowNetwork::new(&edges);
+        let suggest = |amount: u128, options: &FlowOptions| {
+            suggest_trusts_greedily(&a, &d, &edges, &network, U256::from(amount), options).0
+        };
+        assert_eq!(
+            suggest(25, &FlowOptions::default()),
+            vec![
+                SuggestedTrust {
+                    truster: d,
+                    token: c,
+                    flow: U256::from(20),
+                },
+                SuggestedTrust {
+                    truster: d,
+                    token: b,
+                    flow: U256::from(30),
+                }
+            ]
+        );
+        // Only b tokens can be used, which is not enough.
+        let options = FlowOptions {
+            exclude_tokens: [c].into_iter().collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            suggest(25, &options),
+            vec![SuggestedTrust {
+                truster: d,
+                token: b,
+                flow: U256::from(10),
+            }]
+        );
+    }
+}
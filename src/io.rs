@@ -1,31 +1,87 @@
 use std::collections::{BTreeMap, BTreeSet};
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufRead, BufWriter};
 use std::io::{Read, Write};
+use std::path::Path;
 use std::{collections::HashMap, io::BufReader};
 
 use crate::safe_db::db::DB;
-use crate::types::edge::EdgeDB;
+use crate::types::edge::{EdgeDB, GraphModel};
 use crate::types::{Address, Edge, Safe, U256};
 
 pub fn read_edges_binary(path: &String) -> Result<EdgeDB, io::Error> {
-    let mut f = File::open(path)?;
-    let address_index = read_address_index(&mut f)?;
-    read_edges(&mut f, &address_index)
+    read_edges_binary_from(BufReader::new(File::open(path)?))
+}
+
+/// Marks edge databases that start with a format version, followed by all sections.
+/// Older files start with the number of addresses, which is always smaller.
+const EDGES_BINARY_MAGIC: u32 = u32::from_be_bytes(*b"PFED");
+const EDGES_BINARY_VERSION: u8 = 1;
+
+pub fn read_edges_binary_from(mut reader: impl Read) -> Result<EdgeDB, io::Error> {
+    let address_index = match read_u32(&mut reader)? {
+        EDGES_BINARY_MAGIC => match read_u8(&mut reader)? {
+            EDGES_BINARY_VERSION => read_address_index(&mut reader)?,
+            version => {
+                return Err(io::Error::other(format!(
+                    "Unsupported edge database version: {version}"
+                )))
+            }
+        },
+        address_count => read_addresses(&mut reader, address_count)?,
+    };
+    let edges = read_edges(&mut reader, &address_index)?;
+    // token addresses (optional, by token owner)
+    let mut token_addresses = HashMap::new();
+    for _ in 0..read_optional_u32(&mut reader)?.unwrap_or_default() {
+        let token_owner = read_address(&mut reader, &address_index)?;
+        let token = read_address(&mut reader, &address_index)?;
+        token_addresses.insert(token_owner, token);
+    }
+    let mut edges = EdgeDB::new_with_token_addresses(edges, token_addresses);
+    // balances (optional, by holder and token owner)
+    for _ in 0..read_optional_u32(&mut reader)?.unwrap_or_default() {
+        let holder = read_address(&mut reader, &address_index)?;
+        let token_owner = read_address(&mut reader, &address_index)?;
+        let balance = read_u256(&mut reader)?;
+        edges.update_balance(holder, token_owner, balance);
+    }
+    // graph model (optional, Hub version)
+    match read_optional_u8(&mut reader)? {
+        None | Some(1) => {}
+        Some(2) => edges.set_model(GraphModel::V2),
+        Some(version) => {
+            return Err(io::Error::other(format!(
+                "Unsupported graph model version: {version}"
+            )))
+        }
+    }
+    // timestamp of the balances (optional, for demurraged tokens)
+    edges.set_timestamp(read_optional_u64(&mut reader)?);
+    Ok(edges)
 }
 
 pub fn read_edges_csv(path: &String) -> Result<EdgeDB, io::Error> {
+    read_edges_csv_from(BufReader::new(File::open(path)?))
+}
+
+/// Reads edges (`from,to,token,capacity`), balances (`holder,token,balance`),
+/// the graph model (`model,2`) and the timestamp of the balances (`timestamp,<seconds>`)
+/// from CSV lines.
+pub fn read_edges_csv_from(reader: impl BufRead) -> Result<EdgeDB, io::Error> {
     let mut edges = Vec::new();
-    let f = BufReader::new(File::open(path)?);
-    for line in f.lines() {
+    let mut balances = Vec::new();
+    let mut model = GraphModel::V1;
+    let mut timestamp = None;
+    for line in reader.lines() {
         let line = line?;
         match &line.split(',').collect::<Vec<_>>()[..] {
             [] => continue,
             [from, to, token, capacity] => {
-                let from = Address::from(unescape(from));
-                let to = Address::from(unescape(to));
-                let token = Address::from(unescape(token));
-                let capacity = U256::from(unescape(capacity));
+                let from = parse_address(from)?;
+                let to = parse_address(to)?;
+                let token = parse_address(token)?;
+                let capacity = parse_u256(capacity)?;
                 edges.push(Edge {
                     from,
                     to,
@@ -33,25 +89,84 @@ pub fn read_edges_csv(path: &String) -> Result<EdgeDB, io::Error> {
                     capacity,
                 });
             }
+            [holder, token, balance] => {
+                let holder = parse_address(holder)?;
+                let token = parse_address(token)?;
+                let balance = parse_u256(balance)?;
+                balances.push((holder, token, balance));
+            }
+            ["model", "1"] => model = GraphModel::V1,
+            ["model", "2"] => model = GraphModel::V2,
+            ["timestamp", seconds] => {
+                timestamp = Some(
+                    seconds
+                        .parse()
+                        .map_err(|_| io::Error::other(format!("Invalid timestamp: {line}")))?,
+                )
+            }
             _ => {
-                return Result::Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Expected from,to,token,capacity, but got {line}"),
-                ))
+                return Result::Err(io::Error::other(format!(
+                    "Expected from,to,token,capacity or holder,token,balance, but got {line}"
+                )))
             }
         }
     }
-    Ok(EdgeDB::new(edges))
+    let mut edges = EdgeDB::new(edges);
+    for (holder, token, balance) in balances {
+        edges.update_balance(holder, token, balance);
+    }
+    edges.set_model(model);
+    edges.set_timestamp(timestamp);
+    Ok(edges)
 }
 
 pub fn write_edges_binary(edges: &EdgeDB, path: &String) -> Result<(), io::Error> {
-    let mut file = File::create(path)?;
-    let address_index = write_address_index(&mut file, addresses_from_edges(edges))?;
-    write_edges(&mut file, edges, &address_index)
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_edges_binary_to(edges, &mut writer)?;
+    writer.flush()
+}
+
+pub fn write_edges_binary_to(edges: &EdgeDB, mut writer: impl Write) -> Result<(), io::Error> {
+    write_u32(&mut writer, EDGES_BINARY_MAGIC)?;
+    write_u8(&mut writer, EDGES_BINARY_VERSION)?;
+    let address_index = write_address_index(&mut writer, addresses_from_edges(edges))?;
+    write_edges(&mut writer, edges, &address_index)?;
+    // token addresses
+    let token_addresses = edges.token_addresses().iter().collect::<BTreeMap<_, _>>();
+    write_u32(&mut writer, token_addresses.len() as u32)?;
+    for (token_owner, token) in token_addresses {
+        write_address(&mut writer, token_owner, &address_index)?;
+        write_address(&mut writer, token, &address_index)?;
+    }
+    // balances
+    let balances = edges.balances().iter().collect::<BTreeMap<_, _>>();
+    write_u32(&mut writer, balances.len() as u32)?;
+    for ((holder, token_owner), balance) in balances {
+        write_address(&mut writer, holder, &address_index)?;
+        write_address(&mut writer, token_owner, &address_index)?;
+        write_u256(&mut writer, balance)?;
+    }
+    // graph model
+    write_u8(
+        &mut writer,
+        match edges.model() {
+            GraphModel::V1 => 1,
+            GraphModel::V2 => 2,
+        },
+    )?;
+    if let Some(timestamp) = edges.timestamp() {
+        writer.write_all(&timestamp.to_be_bytes())?;
+    }
+    Ok(())
 }
 
 pub fn write_edges_csv(edges: &EdgeDB, path: &String) -> Result<(), io::Error> {
-    let mut file = File::create(path)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_edges_csv_to(edges, &mut writer)?;
+    writer.flush()
+}
+
+pub fn write_edges_csv_to(edges: &EdgeDB, mut writer: impl Write) -> Result<(), io::Error> {
     let mut sorted_edges = edges.edges().clone();
     sorted_edges.sort();
     for Edge {
@@ -61,14 +176,27 @@ pub fn write_edges_csv(edges: &EdgeDB, path: &String) -> Result<(), io::Error> {
         capacity,
     } in sorted_edges
     {
-        writeln!(file, "{from},{to},{token},{capacity}")?;
+        writeln!(writer, "{from},{to},{token},{capacity}")?;
+    }
+    let balances = edges.balances().iter().collect::<BTreeMap<_, _>>();
+    for ((holder, token), balance) in balances {
+        writeln!(writer, "{holder},{token},{balance}")?;
+    }
+    // Omitted for v1 graphs, which older versions can still read.
+    if edges.model() == GraphModel::V2 {
+        writeln!(writer, "model,2")?;
+    }
+    if let Some(timestamp) = edges.timestamp() {
+        writeln!(writer, "timestamp,{timestamp}")?;
     }
     Ok(())
 }
 
 pub fn import_from_safes_binary(path: &str) -> Result<DB, io::Error> {
-    let mut f = File::open(path)?;
+    import_from_safes_binary_from(BufReader::new(File::open(path)?))
+}
 
+pub fn import_from_safes_binary_from(mut f: impl Read) -> Result<DB, io::Error> {
     let mut safes: BTreeMap<Address, Safe> = Default::default();
 
     let address_index = read_address_index(&mut f)?;
@@ -81,12 +209,14 @@ pub fn import_from_safes_binary(path: &str) -> Result<DB, io::Error> {
 
     // trust edges
     for _ in 0..read_u32(&mut f)? {
-        let user = read_address(&mut f, &address_index)?;
-        assert!(user != Address::default());
-        let send_to = read_address(&mut f, &address_index)?;
-        assert!(send_to != Address::default());
+        let user = read_nonzero_address(&mut f, &address_index)?;
+        let send_to = read_nonzero_address(&mut f, &address_index)?;
         let limit_percentage = read_u8(&mut f)?;
-        assert!(limit_percentage <= 100);
+        if limit_percentage > 100 {
+            return Err(invalid_data(format!(
+                "Invalid limit percentage: {limit_percentage}"
+            )));
+        }
 
         if send_to != user && limit_percentage > 0 {
             safes
@@ -99,34 +229,37 @@ pub fn import_from_safes_binary(path: &str) -> Result<DB, io::Error> {
 
     // balances
     for _ in 0..read_u32(&mut f)? {
-        let user = read_address(&mut f, &address_index)?;
-        assert!(user != Address::default());
-        let token_owner = read_address(&mut f, &address_index)?;
-        assert!(token_owner != Address::default());
+        let user = read_nonzero_address(&mut f, &address_index)?;
+        let token = read_nonzero_address(&mut f, &address_index)?;
         let balance = read_u256(&mut f)?;
         if balance != U256::from(0) {
             safes
                 .entry(user)
                 .or_default()
                 .balances
-                .insert(token_owner, balance);
+                .insert(token, balance);
         }
     }
 
-    // we use the safe address as token address
-    let mut token_owner = BTreeMap::default();
-    for (addr, safe) in &mut safes {
-        safe.token_address = *addr;
-        token_owner.insert(*addr, *addr);
+    // tokens (optional)
+    let mut token_owner = BTreeMap::new();
+    for _ in 0..read_optional_u32(&mut f)?.unwrap_or_default() {
+        let token = read_address(&mut f, &address_index)?;
+        let owner = read_address(&mut f, &address_index)?;
+        token_owner.insert(token, owner);
     }
 
-    Ok(DB::new(safes, token_owner))
+    Ok(db_from_safes(safes, token_owner))
 }
 
 pub fn export_safes_to_binary(db: &DB, path: &str) -> Result<(), io::Error> {
-    let mut file = File::create(path)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    export_safes_to_binary_to(db, &mut writer)?;
+    writer.flush()
+}
 
-    let address_index = write_address_index(&mut file, addresses_from_safes(db.safes()))?;
+pub fn export_safes_to_binary_to(db: &DB, mut file: impl Write) -> Result<(), io::Error> {
+    let address_index = write_address_index(&mut file, addresses_from_safes(db))?;
 
     // organizations
     let organizations = db.safes().iter().filter(|s| s.1.organization);
@@ -152,19 +285,219 @@ pub fn export_safes_to_binary(db: &DB, path: &str) -> Result<(), io::Error> {
     let balances = db.safes().iter().flat_map(|(user, safe)| {
         safe.balances
             .iter()
-            .map(|(token_owner, amount)| (*user, token_owner, amount))
+            .map(|(token, amount)| (*user, token, amount))
     });
     write_u32(&mut file, balances.clone().count() as u32)?;
-    for (user, token_owner, amount) in balances {
+    for (user, token, amount) in balances {
         write_address(&mut file, &user, &address_index)?;
-        write_address(&mut file, token_owner, &address_index)?;
+        write_address(&mut file, token, &address_index)?;
         write_u256(&mut file, amount)?;
     }
+
+    // tokens
+    write_u32(&mut file, db.token_owner().len() as u32)?;
+    for (token, owner) in db.token_owner() {
+        write_address(&mut file, token, &address_index)?;
+        write_address(&mut file, owner, &address_index)?;
+    }
     Ok(())
 }
 
-fn read_address_index(file: &mut File) -> Result<HashMap<u32, Address>, io::Error> {
+pub fn import_from_safes_csv(directory: &str) -> Result<DB, io::Error> {
+    let open = |name: &str| -> Result<BufReader<File>, io::Error> {
+        Ok(BufReader::new(File::open(Path::new(directory).join(name))?))
+    };
+    // The tokens table is optional.
+    let tokens: Box<dyn BufRead> = match open("tokens.csv") {
+        Ok(tokens) => Box::new(tokens),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Box::new(io::empty()),
+        Err(e) => return Err(e),
+    };
+    import_from_safes_csv_from(
+        open("organizations.csv")?,
+        open("trusts.csv")?,
+        open("balances.csv")?,
+        tokens,
+    )
+}
+
+/// Reads the safes from four CSV tables:
+/// organizations (`address`), trust edges (`user,send_to,limit_percentage`),
+/// balances (`user,token,balance`) and tokens (`token,owner`).
+pub fn import_from_safes_csv_from(
+    organizations: impl BufRead,
+    trusts: impl BufRead,
+    balances: impl BufRead,
+    tokens: impl BufRead,
+) -> Result<DB, io::Error> {
+    let mut safes: BTreeMap<Address, Safe> = Default::default();
+
+    for line in organizations.lines() {
+        let line = line?;
+        match &line.split(',').collect::<Vec<_>>()[..] {
+            [] | [""] => continue,
+            [org_address] => {
+                let org_address = parse_address(org_address)?;
+                safes.entry(org_address).or_default().organization = true;
+            }
+            _ => {
+                return Result::Err(io::Error::other(format!(
+                    "Expected address, but got {line}"
+                )))
+            }
+        }
+    }
+
+    for line in trusts.lines() {
+        let line = line?;
+        match &line.split(',').collect::<Vec<_>>()[..] {
+            [] | [""] => continue,
+            [user, send_to, limit_percentage] => {
+                let user = parse_address(user)?;
+                let send_to = parse_address(send_to)?;
+                let limit_percentage = unescape(limit_percentage)?
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|p| *p <= 100)
+                    .ok_or_else(|| {
+                        io::Error::other(format!("Invalid limit percentage in {line}"))
+                    })?;
+                if send_to != user && limit_percentage > 0 {
+                    safes
+                        .entry(user)
+                        .or_default()
+                        .limit_percentage
+                        .insert(send_to, limit_percentage);
+                }
+            }
+            _ => {
+                return Result::Err(io::Error::other(format!(
+                    "Expected user,send_to,limit_percentage, but got {line}"
+                )))
+            }
+        }
+    }
+
+    for line in balances.lines() {
+        let line = line?;
+        match &line.split(',').collect::<Vec<_>>()[..] {
+            [] | [""] => continue,
+            [user, token, balance] => {
+                let user = parse_address(user)?;
+                let token = parse_address(token)?;
+                let balance = parse_u256(balance)?;
+                if balance != U256::from(0) {
+                    safes
+                        .entry(user)
+                        .or_default()
+                        .balances
+                        .insert(token, balance);
+                }
+            }
+            _ => {
+                return Result::Err(io::Error::other(format!(
+                    "Expected user,token,balance, but got {line}"
+                )))
+            }
+        }
+    }
+
+    let mut token_owner = BTreeMap::new();
+    for line in tokens.lines() {
+        let line = line?;
+        match &line.split(',').collect::<Vec<_>>()[..] {
+            [] | [""] => continue,
+            [token, owner] => {
+                token_owner.insert(parse_address(token)?, parse_address(owner)?);
+            }
+            _ => {
+                return Result::Err(io::Error::other(format!(
+                    "Expected token,owner, but got {line}"
+                )))
+            }
+        }
+    }
+
+    Ok(db_from_safes(safes, token_owner))
+}
+
+pub fn export_safes_to_csv(db: &DB, directory: &str) -> Result<(), io::Error> {
+    create_dir_all(directory)?;
+    let create = |name: &str| -> Result<BufWriter<File>, io::Error> {
+        Ok(BufWriter::new(File::create(
+            Path::new(directory).join(name),
+        )?))
+    };
+    let mut organizations = create("organizations.csv")?;
+    let mut trusts = create("trusts.csv")?;
+    let mut balances = create("balances.csv")?;
+    let mut tokens = create("tokens.csv")?;
+    export_safes_to_csv_to(
+        db,
+        &mut organizations,
+        &mut trusts,
+        &mut balances,
+        &mut tokens,
+    )?;
+    organizations.flush()?;
+    trusts.flush()?;
+    balances.flush()?;
+    tokens.flush()
+}
+
+pub fn export_safes_to_csv_to(
+    db: &DB,
+    mut organizations: impl Write,
+    mut trusts: impl Write,
+    mut balances: impl Write,
+    mut tokens: impl Write,
+) -> Result<(), io::Error> {
+    for (user, safe) in db.safes() {
+        if safe.organization {
+            writeln!(organizations, "{user}")?;
+        }
+        for (send_to, percentage) in &safe.limit_percentage {
+            writeln!(trusts, "{user},{send_to},{percentage}")?;
+        }
+        for (token, balance) in &safe.balances {
+            writeln!(balances, "{user},{token},{balance}")?;
+        }
+    }
+    for (token, owner) in db.token_owner() {
+        writeln!(tokens, "{token},{owner}")?;
+    }
+    Ok(())
+}
+
+/// Creates the database and assigns the tokens to their owners.
+/// If the data does not contain any tokens (older formats did not distinguish
+/// between tokens and their owners), we use the safe address as token address.
+fn db_from_safes(
+    mut safes: BTreeMap<Address, Safe>,
+    mut token_owner: BTreeMap<Address, Address>,
+) -> DB {
+    if token_owner.is_empty() {
+        for (addr, safe) in &mut safes {
+            safe.token_address = *addr;
+            token_owner.insert(*addr, *addr);
+        }
+    } else {
+        for (token, owner) in &token_owner {
+            safes.entry(*owner).or_default().token_address = *token;
+        }
+    }
+    DB::new(safes, token_owner)
+}
+
+fn read_address_index(file: &mut impl Read) -> Result<HashMap<u32, Address>, io::Error> {
     let address_count = read_u32(file)?;
+    read_addresses(file, address_count)
+}
+
+fn read_addresses(
+    file: &mut impl Read,
+    address_count: u32,
+) -> Result<HashMap<u32, Address>, io::Error> {
     let mut addresses = HashMap::new();
     for i in 0..address_count {
         let mut buf = [0; 20];
@@ -184,10 +517,18 @@ fn addresses_from_edges(edges: &EdgeDB) -> BTreeSet<Address> {
         addresses.insert(*to);
         addresses.insert(*token);
     }
+    for (token_owner, token) in edges.token_addresses() {
+        addresses.insert(*token_owner);
+        addresses.insert(*token);
+    }
+    for (holder, token_owner) in edges.balances().keys() {
+        addresses.insert(*holder);
+        addresses.insert(*token_owner);
+    }
     addresses
 }
 
-fn addresses_from_safes(safes: &BTreeMap<Address, Safe>) -> BTreeSet<Address> {
+fn addresses_from_safes(db: &DB) -> BTreeSet<Address> {
     let mut addresses = BTreeSet::new();
     for (
         user,
@@ -197,7 +538,7 @@ fn addresses_from_safes(safes: &BTreeMap<Address, Safe>) -> BTreeSet<Address> {
             limit_percentage,
             organization: _,
         },
-    ) in safes
+    ) in db.safes()
     {
         addresses.insert(*user);
         addresses.insert(*token_address);
@@ -208,11 +549,15 @@ fn addresses_from_safes(safes: &BTreeMap<Address, Safe>) -> BTreeSet<Address> {
             addresses.insert(*other);
         }
     }
+    for (token, owner) in db.token_owner() {
+        addresses.insert(*token);
+        addresses.insert(*owner);
+    }
     addresses
 }
 
 fn write_address_index(
-    file: &mut File,
+    file: &mut impl Write,
     addresses: BTreeSet<Address>,
 ) -> Result<HashMap<Address, u32>, io::Error> {
     write_u32(file, addresses.len() as u32)?;
@@ -224,46 +569,92 @@ fn write_address_index(
     Ok(index)
 }
 
-fn read_u32(file: &mut File) -> Result<u32, io::Error> {
+fn read_u32(file: &mut impl Read) -> Result<u32, io::Error> {
     let mut buf = [0; 4];
     file.read_exact(&mut buf)?;
     Ok(u32::from_be_bytes(buf))
 }
 
-fn write_u32(file: &mut File, v: u32) -> Result<(), io::Error> {
+/// Reads the length of an optional section at the end of the data.
+/// Returns `None` if the data ends before.
+fn read_optional_u32(file: &mut impl Read) -> Result<Option<u32>, io::Error> {
+    let mut buf = [0; 4];
+    if file.read(&mut buf[..1])? == 0 {
+        return Ok(None);
+    }
+    file.read_exact(&mut buf[1..])?;
+    Ok(Some(u32::from_be_bytes(buf)))
+}
+
+/// Reads an optional byte at the end of the data.
+fn read_optional_u8(file: &mut impl Read) -> Result<Option<u8>, io::Error> {
+    let mut buf = [0; 1];
+    if file.read(&mut buf)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(buf[0]))
+}
+
+fn read_optional_u64(file: &mut impl Read) -> Result<Option<u64>, io::Error> {
+    let mut buf = [0; 8];
+    if file.read(&mut buf[..1])? == 0 {
+        return Ok(None);
+    }
+    file.read_exact(&mut buf[1..])?;
+    Ok(Some(u64::from_be_bytes(buf)))
+}
+
+fn write_u32(file: &mut impl Write, v: u32) -> Result<(), io::Error> {
     let buf = v.to_be_bytes();
     file.write_all(&buf)
 }
 
-fn read_u8(file: &mut File) -> Result<u8, io::Error> {
+fn read_u8(file: &mut impl Read) -> Result<u8, io::Error> {
     let mut buf = [0; 1];
     file.read_exact(&mut buf)?;
     Ok(u8::from_be_bytes(buf))
 }
 
-fn write_u8(file: &mut File, v: u8) -> Result<(), io::Error> {
+fn write_u8(file: &mut impl Write, v: u8) -> Result<(), io::Error> {
     let buf = v.to_be_bytes();
     file.write_all(&buf)
 }
 
 fn read_address(
-    file: &mut File,
+    file: &mut impl Read,
     address_index: &HashMap<u32, Address>,
 ) -> Result<Address, io::Error> {
     let index = read_u32(file)?;
-    Ok(address_index[&index])
+    address_index
+        .get(&index)
+        .copied()
+        .ok_or_else(|| invalid_data(format!("Invalid address index: {index}")))
+}
+
+fn read_nonzero_address(
+    file: &mut impl Read,
+    address_index: &HashMap<u32, Address>,
+) -> Result<Address, io::Error> {
+    let address = read_address(file, address_index)?;
+    if address == Address::default() {
+        return Err(invalid_data("Unexpected zero address"));
+    }
+    Ok(address)
 }
 
 fn write_address(
-    file: &mut File,
+    file: &mut impl Write,
     address: &Address,
     address_index: &HashMap<Address, u32>,
 ) -> Result<(), io::Error> {
     write_u32(file, *address_index.get(address).unwrap())
 }
 
-fn read_u256(file: &mut File) -> Result<U256, io::Error> {
+fn read_u256(file: &mut impl Read) -> Result<U256, io::Error> {
     let length = read_u8(file)? as usize;
+    if length > 32 {
+        return Err(invalid_data(format!("Invalid length of a U256: {length}")));
+    }
     let mut bytes = [0u8; 32];
     file.read_exact(&mut bytes[32 - length..32])?;
     let high = u128::from_be_bytes(*<&[u8; 16]>::try_from(&bytes[0..16]).unwrap());
@@ -271,7 +662,7 @@ fn read_u256(file: &mut File) -> Result<U256, io::Error> {
     Ok(U256::new(high, low))
 }
 
-fn write_u256(file: &mut File, v: &U256) -> Result<(), io::Error> {
+fn write_u256(file: &mut impl Write, v: &U256) -> Result<(), io::Error> {
     let v_bytes = v.to_bytes();
     if v_bytes.is_empty() {
         file.write_all(&[1, 0])
@@ -281,7 +672,10 @@ fn write_u256(file: &mut File, v: &U256) -> Result<(), io::Error> {
     }
 }
 
-fn read_edges(file: &mut File, address_index: &HashMap<u32, Address>) -> Result<EdgeDB, io::Error> {
+fn read_edges(
+    file: &mut impl Read,
+    address_index: &HashMap<u32, Address>,
+) -> Result<Vec<Edge>, io::Error> {
     let edge_count = read_u32(file)?;
     let mut edges = Vec::new();
     for _i in 0..edge_count {
@@ -296,11 +690,11 @@ fn read_edges(file: &mut File, address_index: &HashMap<u32, Address>) -> Result<
             capacity,
         });
     }
-    Ok(EdgeDB::new(edges))
+    Ok(edges)
 }
 
 fn write_edges(
-    file: &mut File,
+    file: &mut impl Write,
     edges: &EdgeDB,
     address_index: &HashMap<Address, u32>,
 ) -> Result<(), io::Error> {
@@ -322,12 +716,282 @@ fn write_edges(
     Ok(())
 }
 
-fn unescape(input: &str) -> &str {
+fn unescape(input: &str) -> Result<&str, io::Error> {
     match input.chars().next() {
-        Some('"') | Some('\'') => {
-            assert!(input.len() >= 2 && input.chars().last() == input.chars().next());
-            &input[1..input.len() - 1]
+        Some(quote @ ('"' | '\'')) => {
+            if input.len() < 2 || !input.ends_with(quote) {
+                return Err(invalid_data(format!("Unterminated quote: {input}")));
+            }
+            Ok(&input[1..input.len() - 1])
+        }
+        _ => Ok(input),
+    }
+}
+
+fn parse_address(input: &str) -> Result<Address, io::Error> {
+    unescape(input)?.parse().map_err(invalid_data)
+}
+
+fn parse_u256(input: &str) -> Result<U256, io::Error> {
+    unescape(input)?.parse().map_err(invalid_data)
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edges() -> EdgeDB {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let b = Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37");
+        let c = Address::from("0x33cEDde51198D1773590311E2A340DC06B24cB37");
+        let mut edges = EdgeDB::new(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: a,
+                capacity: U256::from(10),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: a,
+                capacity: U256::from("0x100000000000000000000000000000000"),
+            },
+        ]);
+        edges.update_balance(a, a, U256::from(7));
+        edges.update_balance(b, a, U256::from(20));
+        edges
+    }
+
+    fn safes() -> DB {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let b = Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37");
+        let org = Address::from("0x33cEDde51198D1773590311E2A340DC06B24cB37");
+        let token_a = Address::from("0xaa7EDde51198D1773590311E2A340DC06B24cB37");
+        let token_b = Address::from("0xbbc16ce62d26fd51582a646e2e30a3267b1e6d7e");
+        let mut safes = BTreeMap::new();
+        safes.insert(
+            a,
+            Safe {
+                balances: [(token_a, U256::from(100)), (token_b, U256::from(7))].into(),
+                limit_percentage: [(b, 50)].into(),
+                ..Default::default()
+            },
+        );
+        safes.insert(
+            b,
+            Safe {
+                balances: [(token_b, U256::from(200))].into(),
+                limit_percentage: [(a, 100), (org, 100)].into(),
+                ..Default::default()
+            },
+        );
+        safes.insert(
+            org,
+            Safe {
+                organization: true,
+                ..Default::default()
+            },
+        );
+        db_from_safes(safes, [(token_a, a), (token_b, b)].into())
+    }
+
+    fn assert_same_safes(x: &DB, y: &DB) {
+        assert_eq!(format!("{:?}", x.safes()), format!("{:?}", y.safes()));
+        assert_eq!(x.token_owner(), y.token_owner());
+        assert_eq!(x.edges().edges(), y.edges().edges());
+    }
+
+    #[test]
+    fn safes_binary_roundtrip() {
+        let mut buffer = Vec::new();
+        export_safes_to_binary_to(&safes(), &mut buffer).unwrap();
+        let read = import_from_safes_binary_from(buffer.as_slice()).unwrap();
+        assert_same_safes(&read, &safes());
+    }
+
+    #[test]
+    fn safes_binary_without_tokens() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let mut buffer = Vec::new();
+        export_safes_to_binary_to(&safes(), &mut buffer).unwrap();
+        // Strip the token section to obtain the format without token addresses.
+        let token_section_size = 4 + 2 * 2 * 4;
+        buffer.truncate(buffer.len() - token_section_size);
+        let read = import_from_safes_binary_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.safes()[&a].token_address, a);
+        assert_eq!(read.token_owner()[&a], a);
+    }
+
+    #[test]
+    fn token_addresses() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let token_a = Address::from("0xaa7EDde51198D1773590311E2A340DC06B24cB37");
+        let db = safes();
+        assert_eq!(db.safes()[&a].token_address, token_a);
+        assert_eq!(db.edges().token_address(&a), Some(&token_a));
+        let mut buffer = Vec::new();
+        write_edges_binary_to(db.edges(), &mut buffer).unwrap();
+        let read = read_edges_binary_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.token_address(&a), Some(&token_a));
+    }
+
+    #[test]
+    fn safes_csv_roundtrip() {
+        let (mut organizations, mut trusts, mut balances, mut tokens) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        export_safes_to_csv_to(
+            &safes(),
+            &mut organizations,
+            &mut trusts,
+            &mut balances,
+            &mut tokens,
+        )
+        .unwrap();
+        let read = import_from_safes_csv_from(
+            organizations.as_slice(),
+            trusts.as_slice(),
+            balances.as_slice(),
+            tokens.as_slice(),
+        )
+        .unwrap();
+        assert_same_safes(&read, &safes());
+    }
+
+    #[test]
+    fn edges_binary_roundtrip() {
+        let mut buffer = Vec::new();
+        write_edges_binary_to(&edges(), &mut buffer).unwrap();
+        let read = read_edges_binary_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.edges(), edges().edges());
+        assert_eq!(read.balances(), edges().balances());
+    }
+
+    #[test]
+    fn edges_binary_without_balances() {
+        let mut buffer = Vec::new();
+        write_edges_binary_to(&edges(), &mut buffer).unwrap();
+        // Remove the model, the balances section and the empty token addresses section.
+        buffer.truncate(buffer.len() - 1 - 4 - 2 * (2 * 4 + 2) - 4);
+        let read = read_edges_binary_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.edges(), edges().edges());
+        assert!(read.balances().is_empty());
+    }
+
+    #[test]
+    fn edges_binary_versions() {
+        let mut buffer = Vec::new();
+        write_edges_binary_to(&edges(), &mut buffer).unwrap();
+        assert_eq!(&buffer[..5], b"PFED\x01");
+        // Files of older versions start directly with the address index.
+        let read = read_edges_binary_from(&buffer[5..]).unwrap();
+        assert_eq!(read.edges(), edges().edges());
+        buffer[4] = 2;
+        let error = read_edges_binary_from(buffer.as_slice()).unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported edge database version: 2");
+    }
+
+    #[test]
+    fn edges_binary_model() {
+        let mut edges = edges();
+        edges.set_model(GraphModel::V2);
+        let mut buffer = Vec::new();
+        write_edges_binary_to(&edges, &mut buffer).unwrap();
+        let read = read_edges_binary_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.model(), GraphModel::V2);
+        assert_eq!(read.timestamp(), None);
+        edges.set_timestamp(Some(1700000000));
+        buffer.clear();
+        write_edges_binary_to(&edges, &mut buffer).unwrap();
+        let read = read_edges_binary_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.timestamp(), Some(1700000000));
+        buffer.truncate(buffer.len() - 8);
+        *buffer.last_mut().unwrap() = 7;
+        assert!(read_edges_binary_from(buffer.as_slice()).is_err());
+    }
+
+    #[test]
+    fn edges_csv_roundtrip() {
+        let mut buffer = Vec::new();
+        write_edges_csv_to(&edges(), &mut buffer).unwrap();
+        let read = read_edges_csv_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.edges(), edges().edges());
+        assert_eq!(read.balances(), edges().balances());
+    }
+
+    #[test]
+    fn edges_csv_model() {
+        let mut edges = edges();
+        edges.set_model(GraphModel::V2);
+        edges.set_timestamp(Some(1700000000));
+        let mut buffer = Vec::new();
+        write_edges_csv_to(&edges, &mut buffer).unwrap();
+        let read = read_edges_csv_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.model(), GraphModel::V2);
+        assert_eq!(read.timestamp(), Some(1700000000));
+        assert_eq!(read.edges(), edges.edges());
+    }
+
+    #[test]
+    fn edges_csv_invalid() {
+        assert!(read_edges_csv_from("0x1,0x2\n".as_bytes()).is_err());
+        assert!(read_edges_csv_from("model,3\n".as_bytes()).is_err());
+        assert!(read_edges_csv_from("timestamp,x\n".as_bytes()).is_err());
+        assert!(read_edges_csv_from("0x1,0x2,0x3,10\n".as_bytes()).is_err());
+        let a = "0x11C7e86fF693e9032A0F41711b5581a04b26Be2E";
+        for line in [
+            format!("{a},{a},{a},-1"),
+            format!("{a},{a},{a},0x1g"),
+            format!("\"{a},{a},{a},1"),
+            format!("{a},{a},1x"),
+        ] {
+            let error = read_edges_csv_from(line.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn edges_binary_invalid() {
+        let mut buffer = Vec::new();
+        write_edges_binary_to(&edges(), &mut buffer).unwrap();
+        // The first edge starts after the magic number, version and three addresses.
+        let edge = 4 + 1 + 4 + 3 * 20 + 4;
+        let mut invalid_index = buffer.clone();
+        invalid_index[edge..edge + 4].copy_from_slice(&7u32.to_be_bytes());
+        let error = read_edges_binary_from(invalid_index.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let mut invalid_length = buffer.clone();
+        invalid_length[edge + 3 * 4] = 33;
+        let error = read_edges_binary_from(invalid_length.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        buffer.truncate(edge + 2);
+        assert!(read_edges_binary_from(buffer.as_slice()).is_err());
+    }
+
+    #[test]
+    fn safes_binary_invalid() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let mut data = Vec::new();
+        write_address_index(&mut data, [Address::default(), a].into()).unwrap();
+        let mut zero_address = data.clone();
+        // no organizations, one trust edge from the zero address
+        for v in [0, 1, 0, 1] {
+            write_u32(&mut zero_address, v).unwrap();
+        }
+        write_u8(&mut zero_address, 50).unwrap();
+        let error = import_from_safes_binary_from(zero_address.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let mut invalid_percentage = data;
+        // no organizations, one trust edge with more than 100 percent
+        for v in [0, 1, 1, 1] {
+            write_u32(&mut invalid_percentage, v).unwrap();
         }
-        _ => input,
+        write_u8(&mut invalid_percentage, 101).unwrap();
+        let error = import_from_safes_binary_from(invalid_percentage.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     }
 }
@@ -1,5 +1,6 @@
 pub mod graph;
 pub mod io;
+pub mod logging;
 pub mod safe_db;
 pub mod server;
 pub mod types;
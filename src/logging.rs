@@ -0,0 +1,168 @@
+use std::fmt::Write as _;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use json::JsonValue;
+use log::kv::{Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// The output format of the logger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// `<time> <level> <target>: <message> key=value ...`
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields as members.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid log format: {s}, expected text or json")),
+        }
+    }
+}
+
+/// A logger writing one line per record to stderr. The library only emits
+/// records through the `log` macros, it is up to the binaries to install a logger.
+struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+}
+
+/// Installs the logger for records up to `level`.
+pub fn init(level: LevelFilter, format: LogFormat) -> Result<(), SetLoggerError> {
+    log::set_boxed_logger(Box::new(Logger { level, format }))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let line = format_record(self.format, timestamp(), record);
+            let _ = writeln!(std::io::stderr().lock(), "{line}");
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Seconds since the Unix epoch, with milliseconds.
+fn timestamp() -> f64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_millis() as f64 / 1000.0
+}
+
+fn format_record(format: LogFormat, timestamp: f64, record: &Record) -> String {
+    let mut fields = Fields(vec![]);
+    let _ = record.key_values().visit(&mut fields);
+    match format {
+        LogFormat::Text => {
+            let mut line = format!(
+                "{timestamp:.3} {} {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            // Strings are quoted as in JSON.
+            for (key, value) in fields.0 {
+                write!(line, " {key}={}", value.dump()).expect("");
+            }
+            line
+        }
+        LogFormat::Json => {
+            let mut line = json::object! {
+                time: timestamp,
+                level: record.level().as_str(),
+                target: record.target(),
+                message: record.args().to_string(),
+            };
+            for (key, value) in fields.0 {
+                line[key.as_str()] = value;
+            }
+            line.dump()
+        }
+    }
+}
+
+/// Collects the structured fields of a record.
+struct Fields(Vec<(String, JsonValue)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = if let Some(v) = value.to_u64() {
+            v.into()
+        } else if let Some(v) = value.to_i64() {
+            v.into()
+        } else if let Some(v) = value.to_f64() {
+            v.into()
+        } else if let Some(v) = value.to_bool() {
+            v.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use log::Level;
+
+    fn format(format: LogFormat) -> String {
+        let fields: &[(&str, Value)] = &[
+            ("method", Value::from("compute_transfer")),
+            ("duration_ms", Value::from(12u64)),
+        ];
+        format_record(
+            format,
+            1.5,
+            &Record::builder()
+                .args(format_args!("Handled request"))
+                .level(Level::Info)
+                .target("pathfinder2::server")
+                .key_values(&fields)
+                .build(),
+        )
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            format(LogFormat::Text),
+            "1.500 INFO pathfinder2::server: Handled request method=\"compute_transfer\" duration_ms=12"
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            json::parse(&format(LogFormat::Json)).unwrap(),
+            json::object! {
+                time: 1.5,
+                level: "INFO",
+                target: "pathfinder2::server",
+                message: "Handled request",
+                method: "compute_transfer",
+                duration_ms: 12,
+            }
+        );
+    }
+}
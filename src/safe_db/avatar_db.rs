@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+
+use crate::types::demurrage::Demurrage;
+use crate::types::edge::{EdgeDB, GraphModel};
+use crate::types::{Address, Avatar, Edge, U256};
+
+/// The state of the Circles v2 Hub, the counterpart of `DB` for v1.
+#[derive(Default, Debug)]
+pub struct AvatarDB {
+    avatars: BTreeMap<Address, Avatar>,
+    /// The time the state was taken at (unix timestamp). Balances
+    /// are demurraged as of this time.
+    timestamp: u64,
+    edges: EdgeDB,
+}
+
+impl AvatarDB {
+    pub fn new(avatars: BTreeMap<Address, Avatar>, timestamp: u64) -> AvatarDB {
+        log::debug!("{} avatars", avatars.len());
+        let mut db = AvatarDB {
+            avatars,
+            timestamp,
+            ..Default::default()
+        };
+        db.edges = db.edges_as_of(timestamp);
+        db
+    }
+
+    pub fn avatars(&self) -> &BTreeMap<Address, Avatar> {
+        &self.avatars
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn edges(&self) -> &EdgeDB {
+        &self.edges
+    }
+
+    /// Computes the edges as they will be at time `as_of`, i.e. with
+    /// trusts that expire before removed and demurrage applied to the balances.
+    pub fn edges_as_of(&self, as_of: u64) -> EdgeDB {
+        let demurrage = Demurrage::between(self.timestamp, as_of);
+        // Avatars that accept the tokens of an avatar.
+        let mut accepted_by: BTreeMap<Address, Vec<Address>> = BTreeMap::new();
+        for (address, avatar) in &self.avatars {
+            for trustee in avatar.trusts.keys() {
+                if *trustee != *address && avatar.trusts_at(trustee, as_of) {
+                    accepted_by.entry(*trustee).or_default().push(*address);
+                }
+            }
+            // Avatars always accept their own tokens.
+            if avatar.has_token() {
+                accepted_by.entry(*address).or_default().push(*address);
+            }
+        }
+        let mut edges = vec![];
+        let mut balances = vec![];
+        for (holder, avatar) in &self.avatars {
+            for (token, balance) in &avatar.balances {
+                let balance = &demurrage.apply(*balance);
+                if *balance == U256::from(0) || (holder == token && avatar.is_group()) {
+                    continue;
+                }
+                // Trust is binary, so the send limit is the full balance.
+                let edge_count = edges.len();
+                for receiver in accepted_by.get(token).into_iter().flatten() {
+                    if *receiver != *holder {
+                        edges.push(Edge {
+                            from: *holder,
+                            to: *receiver,
+                            token: *token,
+                            capacity: *balance,
+                        });
+                    }
+                }
+                if edges.len() > edge_count {
+                    balances.push((*holder, *token, *balance));
+                }
+            }
+        }
+        // Groups mint their tokens from the collateral they accept
+        // and send them to everyone accepting the group token.
+        // The mint policy of the group is not evaluated, so minting is assumed
+        // to be unlimited, which can over-estimate the flow through groups.
+        for (group, avatar) in &self.avatars {
+            if !avatar.is_group() {
+                continue;
+            }
+            for collateral in avatar.trusts.keys() {
+                if *collateral != *group && avatar.trusts_at(collateral, as_of) {
+                    edges.push(Edge {
+                        from: *group,
+                        to: *group,
+                        token: *collateral,
+                        capacity: U256::MAX,
+                    });
+                }
+            }
+            for receiver in accepted_by.get(group).into_iter().flatten() {
+                if *receiver != *group {
+                    edges.push(Edge {
+                        from: *group,
+                        to: *receiver,
+                        token: *group,
+                        capacity: U256::MAX,
+                    });
+                }
+            }
+            // Without minting, the group can only send the tokens it holds.
+            balances.push((*group, *group, demurrage.apply(avatar.balance(group))));
+        }
+        let mut edge_db = EdgeDB::new(edges);
+        edge_db.set_model(GraphModel::V2);
+        edge_db.set_timestamp(Some(as_of));
+        for (holder, token, balance) in balances {
+            edge_db.update_balance(holder, token, balance);
+        }
+        edge_db
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::{compute_flow, compute_flow_with_options, FlowOptions};
+    use crate::types::AvatarKind;
+
+    fn avatar(kind: AvatarKind, balances: &[(Address, u128)], trusts: &[(Address, u64)]) -> Avatar {
+        Avatar {
+            kind,
+            balances: balances
+                .iter()
+                .map(|(token, balance)| (*token, U256::from(*balance)))
+                .collect(),
+            trusts: trusts.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn edges() {
+        let (a, b, c, org) = (
+            Address::from([1; 20]),
+            Address::from([2; 20]),
+            Address::from([3; 20]),
+            Address::from([4; 20]),
+        );
+        let db = AvatarDB::new(
+            BTreeMap::from([
+                (a, avatar(AvatarKind::Human, &[(a, 10), (c, 4)], &[])),
+                // The trust in c has expired.
+                (b, avatar(AvatarKind::Human, &[], &[(a, 100), (c, 99)])),
+                (c, avatar(AvatarKind::Human, &[], &[(a, 100)])),
+                (org, avatar(AvatarKind::Organization, &[], &[(a, 100)])),
+            ]),
+            100,
+        );
+        let mut edges = db
+            .edges()
+            .outgoing(&a)
+            .into_iter()
+            .map(|e| (e.to, e.token, e.capacity))
+            .collect::<Vec<_>>();
+        edges.sort();
+        assert_eq!(
+            edges,
+            vec![
+                (b, a, U256::from(10)),
+                (c, a, U256::from(10)),
+                // return to owner
+                (c, c, U256::from(4)),
+                (org, a, U256::from(10)),
+            ]
+        );
+        assert_eq!(db.edges().balance(&a, &a), Some(U256::from(10)));
+        assert_eq!(db.edges().model(), GraphModel::V2);
+    }
+
+    #[test]
+    fn unlimited_trust() {
+        let (a, b, c, d) = (
+            Address::from([1; 20]),
+            Address::from([2; 20]),
+            Address::from([3; 20]),
+            Address::from([4; 20]),
+        );
+        // a swaps the tokens of b and c for its own tokens held by them,
+        // and d accepts a's tokens from both b and c.
+        let db = AvatarDB::new(
+            BTreeMap::from([
+                (a, avatar(AvatarKind::Human, &[(b, 10), (c, 20)], &[])),
+                (b, avatar(AvatarKind::Human, &[(a, 10)], &[])),
+                (c, avatar(AvatarKind::Human, &[(a, 20)], &[])),
+                (d, avatar(AvatarKind::Human, &[], &[(a, 100)])),
+            ]),
+            0,
+        );
+        let (flow, _) = compute_flow(&a, &d, db.edges(), U256::MAX, None, None);
+        assert_eq!(flow, U256::from(30));
+    }
+
+    #[test]
+    fn as_of() {
+        let (a, b) = (Address::from([1; 20]), Address::from([2; 20]));
+        let one = U256::from(1_000_000_000_000_000_000);
+        let timestamp = 1700000000;
+        let year = 365 * 24 * 60 * 60;
+        let db = AvatarDB::new(
+            BTreeMap::from([
+                (
+                    a,
+                    avatar(AvatarKind::Human, &[(a, 100_000_000_000_000_000_000)], &[]),
+                ),
+                (
+                    b,
+                    avatar(AvatarKind::Human, &[], &[(a, timestamp + year / 2)]),
+                ),
+            ]),
+            timestamp,
+        );
+        let (flow, _) = compute_flow(&a, &b, db.edges(), U256::MAX, None, None);
+        assert_eq!(flow, U256::from(100) * one);
+
+        // After a year, 7% are gone and the trust has expired.
+        let later = db.edges_as_of(timestamp + year);
+        assert_eq!(later.edge_count(), 0);
+        let later = db.edges_as_of(timestamp + year / 4);
+        let balance = later.balance(&a, &a).unwrap();
+        assert!(balance < U256::from(99) * one);
+        assert!(balance > U256::from(98) * one);
+
+        // The same result computed from the original edges.
+        let options = FlowOptions {
+            as_of: Some(timestamp + year / 4),
+            ..Default::default()
+        };
+        let (flow, transfers) =
+            compute_flow_with_options(&a, &b, db.edges(), U256::MAX, None, None, &options);
+        assert_eq!(flow, balance);
+        assert_eq!(transfers[0].capacity, balance);
+    }
+
+    #[test]
+    fn group_mint() {
+        let (a, b, group, r) = (
+            Address::from([1; 20]),
+            Address::from([2; 20]),
+            Address::from([3; 20]),
+            Address::from([4; 20]),
+        );
+        let mint_policy = Address::from([9; 20]);
+        // r only accepts the group token, which the group mints from a's and b's tokens.
+        let db = AvatarDB::new(
+            BTreeMap::from([
+                (a, avatar(AvatarKind::Human, &[(a, 10), (b, 5)], &[])),
+                (b, avatar(AvatarKind::Human, &[], &[])),
+                (
+                    group,
+                    avatar(
+                        AvatarKind::Group { mint_policy },
+                        &[],
+                        &[(a, 100), (b, 100)],
+                    ),
+                ),
+                (r, avatar(AvatarKind::Human, &[], &[(group, 100)])),
+            ]),
+            0,
+        );
+        let (flow, mut transfers) = compute_flow(&a, &r, db.edges(), U256::MAX, None, None);
+        assert_eq!(flow, U256::from(15));
+        transfers.sort();
+        assert_eq!(
+            transfers,
+            vec![
+                Edge {
+                    from: a,
+                    to: group,
+                    token: a,
+                    capacity: U256::from(10)
+                },
+                Edge {
+                    from: a,
+                    to: group,
+                    token: b,
+                    capacity: U256::from(5)
+                },
+                Edge {
+                    from: group,
+                    to: r,
+                    token: group,
+                    capacity: U256::from(15)
+                },
+            ]
+        );
+        // The group cannot send tokens without collateral.
+        let (flow, _) = compute_flow(&group, &r, db.edges(), U256::MAX, None, None);
+        assert_eq!(flow, U256::from(0));
+    }
+}
@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use crate::types::{Address, Avatar, AvatarKind, U256};
+
+use super::avatar_db::AvatarDB;
+
+pub fn import_from_avatars_json(file: &str) -> Result<AvatarDB, io::Error> {
+    import_from_avatars_json_from(BufReader::new(File::open(file)?))
+}
+
+/// Imports the state of the v2 Hub, i.e. a `timestamp` and
+/// a list of `avatars` with their trust relations and balances.
+pub fn import_from_avatars_json_from(reader: impl Read) -> Result<AvatarDB, io::Error> {
+    let state: JsonState = serde_json::from_reader(reader)?;
+    let mut avatars = BTreeMap::new();
+    for avatar in state.avatars {
+        let (address, avatar) = avatar_from_json(avatar).map_err(io::Error::other)?;
+        if avatars.insert(address, avatar).is_some() {
+            return Err(io::Error::other(format!("Duplicate avatar {address}")));
+        }
+    }
+    Ok(AvatarDB::new(avatars, state.timestamp))
+}
+
+pub fn export_avatars_to_json(db: &AvatarDB, file: &str) -> Result<(), io::Error> {
+    let mut writer = BufWriter::new(File::create(file)?);
+    export_avatars_to_json_to(db, &mut writer)?;
+    writer.flush()
+}
+
+pub fn export_avatars_to_json_to(db: &AvatarDB, writer: impl Write) -> Result<(), io::Error> {
+    let state = JsonState {
+        timestamp: db.timestamp(),
+        avatars: db
+            .avatars()
+            .iter()
+            .map(|(address, avatar)| JsonAvatar {
+                address: address.to_string(),
+                kind: match &avatar.kind {
+                    AvatarKind::Human => JsonAvatarKind::Human,
+                    AvatarKind::Organization => JsonAvatarKind::Organization,
+                    AvatarKind::Group { mint_policy } => JsonAvatarKind::Group {
+                        mint_policy: mint_policy.to_string(),
+                    },
+                },
+                trusts: avatar
+                    .trusts
+                    .iter()
+                    .map(|(trustee, expiry)| JsonTrust {
+                        trustee: trustee.to_string(),
+                        expiry: *expiry,
+                    })
+                    .collect(),
+                balances: avatar
+                    .balances
+                    .iter()
+                    .map(|(token, amount)| JsonBalance {
+                        token: token.to_string(),
+                        amount: amount.to_decimal(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+    serde_json::to_writer(writer, &state)?;
+    Ok(())
+}
+
+fn avatar_from_json(avatar: JsonAvatar) -> Result<(Address, Avatar), String> {
+    let address = avatar.address.parse::<Address>()?;
+    let kind = match avatar.kind {
+        JsonAvatarKind::Human => AvatarKind::Human,
+        JsonAvatarKind::Organization => AvatarKind::Organization,
+        JsonAvatarKind::Group { mint_policy } => AvatarKind::Group {
+            mint_policy: mint_policy.parse()?,
+        },
+    };
+    let trusts = avatar
+        .trusts
+        .into_iter()
+        .map(|trust| Ok((trust.trustee.parse()?, trust.expiry)))
+        .collect::<Result<_, String>>()?;
+    let balances = avatar
+        .balances
+        .into_iter()
+        .map(|balance| Ok((balance.token.parse()?, balance.amount.parse::<U256>()?)))
+        .collect::<Result<_, String>>()?;
+    Ok((
+        address,
+        Avatar {
+            kind,
+            balances,
+            trusts,
+        },
+    ))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonState {
+    timestamp: u64,
+    avatars: Vec<JsonAvatar>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonAvatar {
+    address: String,
+    #[serde(flatten)]
+    kind: JsonAvatarKind,
+    #[serde(default)]
+    trusts: Vec<JsonTrust>,
+    #[serde(default)]
+    balances: Vec<JsonBalance>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum JsonAvatarKind {
+    Human,
+    Organization,
+    #[serde(rename_all = "camelCase")]
+    Group {
+        mint_policy: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonTrust {
+    trustee: String,
+    expiry: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonBalance {
+    token: String,
+    amount: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STATE: &str = r#"{
+        "timestamp": 1700000000,
+        "avatars": [
+            {
+                "address": "0x11C7e86fF693e9032A0F41711b5581a04b26Be2E",
+                "type": "human",
+                "balances": [{"token": "0x11C7e86fF693e9032A0F41711b5581a04b26Be2E", "amount": "1000"}]
+            },
+            {
+                "address": "0x22cEDde51198D1773590311E2A340DC06B24cB37",
+                "type": "organization",
+                "trusts": [{"trustee": "0x11C7e86fF693e9032A0F41711b5581a04b26Be2E", "expiry": 1800000000}]
+            },
+            {
+                "address": "0x33cEDde51198D1773590311E2A340DC06B24cB37",
+                "type": "group",
+                "mintPolicy": "0x44cEDde51198D1773590311E2A340DC06B24cB37"
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn import() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let org = Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37");
+        let group = Address::from("0x33cEDde51198D1773590311E2A340DC06B24cB37");
+        let db = import_from_avatars_json_from(STATE.as_bytes()).unwrap();
+        assert_eq!(db.timestamp(), 1700000000);
+        assert_eq!(db.avatars()[&org].kind, AvatarKind::Organization);
+        assert_eq!(
+            db.avatars()[&group].kind,
+            AvatarKind::Group {
+                mint_policy: Address::from("0x44cEDde51198D1773590311E2A340DC06B24cB37")
+            }
+        );
+        assert_eq!(db.avatars()[&a].balance(&a), U256::from(1000));
+        assert_eq!(db.edges().edge_count(), 1);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let db = import_from_avatars_json_from(STATE.as_bytes()).unwrap();
+        let mut buffer = Vec::new();
+        export_avatars_to_json_to(&db, &mut buffer).unwrap();
+        let read = import_from_avatars_json_from(buffer.as_slice()).unwrap();
+        assert_eq!(read.timestamp(), db.timestamp());
+        assert_eq!(read.avatars().len(), db.avatars().len());
+        for (address, avatar) in db.avatars() {
+            assert_eq!(read.avatars()[address].kind, avatar.kind);
+            assert_eq!(read.avatars()[address].trusts, avatar.trusts);
+            assert_eq!(read.avatars()[address].balances, avatar.balances);
+        }
+        assert_eq!(read.edges().edges(), db.edges().edges());
+    }
+
+    #[test]
+    fn invalid() {
+        let state = STATE.replace("\"type\": \"organization\"", "\"type\": \"bank\"");
+        assert!(import_from_avatars_json_from(state.as_bytes()).is_err());
+        let state = STATE.replace("\"1000\"", "\"-1\"");
+        assert!(import_from_avatars_json_from(state.as_bytes()).is_err());
+    }
+}
@@ -2,16 +2,20 @@ use std::collections::BTreeMap;
 
 use crate::types::{edge::EdgeDB, Address, Edge, Safe, U256};
 
+use super::hub_events::{HubEvent, LogPosition};
+
 #[derive(Default, Debug)]
 pub struct DB {
     safes: BTreeMap<Address, Safe>,
     token_owner: BTreeMap<Address, Address>,
     edges: EdgeDB,
+    /// The block number and log index of the last applied Hub event.
+    last_event: Option<LogPosition>,
 }
 
 impl DB {
     pub fn new(safes: BTreeMap<Address, Safe>, token_owner: BTreeMap<Address, Address>) -> DB {
-        println!("{} safes, {} tokens", safes.len(), token_owner.len());
+        log::debug!("{} safes, {} tokens", safes.len(), token_owner.len());
         let mut db = DB {
             safes,
             token_owner,
@@ -25,31 +29,131 @@ impl DB {
         &self.safes
     }
 
+    pub fn token_owner(&self) -> &BTreeMap<Address, Address> {
+        &self.token_owner
+    }
+
     pub fn edges(&self) -> &EdgeDB {
         &self.edges
     }
 
+    /// The block number and log index of the last applied Hub event, if any.
+    pub fn last_event(&self) -> Option<LogPosition> {
+        self.last_event
+    }
+
+    /// Applies the state changes of the events, which have to be in block order,
+    /// and re-computes the edges. Events at or before the last applied event are
+    /// skipped, so that overlapping ranges of logs can be applied again.
+    /// Returns the number of applied events.
+    pub fn apply_hub_events(&mut self, events: &[(LogPosition, HubEvent)]) -> usize {
+        let events = events
+            .iter()
+            .filter(|(position, _)| self.last_event.is_none_or(|last| *position > last))
+            .collect::<Vec<_>>();
+        if let Some((position, _)) = events.last() {
+            self.last_event = Some(*position);
+        }
+        // The signup bonus is minted before the Signup event is emitted,
+        // so the tokens have to be known before any transfer is applied.
+        for (_, event) in &events {
+            if let HubEvent::Signup { user, token } = event {
+                self.token_owner.insert(*token, *user);
+            }
+        }
+        for (_, event) in &events {
+            match event {
+                HubEvent::Signup { user, token } => {
+                    self.safes.entry(*user).or_default().token_address = *token;
+                    self.token_owner.insert(*token, *user);
+                }
+                HubEvent::OrganizationSignup { organization } => {
+                    self.safes.entry(*organization).or_default().organization = true;
+                }
+                HubEvent::Trust {
+                    can_send_to,
+                    user,
+                    limit,
+                } => {
+                    if *can_send_to == *user {
+                        continue;
+                    }
+                    let limits = &mut self.safes.entry(*user).or_default().limit_percentage;
+                    if *limit == 0 {
+                        limits.remove(can_send_to);
+                    } else {
+                        limits.insert(*can_send_to, *limit);
+                    }
+                }
+                HubEvent::HubTransfer { .. } => {}
+                HubEvent::Transfer {
+                    token,
+                    from,
+                    to,
+                    amount,
+                } => {
+                    if !self.token_owner.contains_key(token) {
+                        // Not a Circles token.
+                        continue;
+                    }
+                    if *from != Address::default() {
+                        let balances = &mut self.safes.entry(*from).or_default().balances;
+                        let balance = balances.entry(*token).or_default();
+                        *balance = if *balance > *amount {
+                            *balance - *amount
+                        } else {
+                            U256::from(0)
+                        };
+                        if *balance == U256::from(0) {
+                            balances.remove(token);
+                        }
+                    }
+                    if *to != Address::default() {
+                        *self
+                            .safes
+                            .entry(*to)
+                            .or_default()
+                            .balances
+                            .entry(*token)
+                            .or_default() += *amount;
+                    }
+                }
+            }
+        }
+        self.compute_edges();
+        events.len()
+    }
+
     fn compute_edges(&mut self) {
         let mut edges = vec![];
+        // Balances of the senders of the edges, by token owner.
+        let mut balances = vec![];
         for (user, safe) in &self.safes {
             // trust connections
-            for (send_to, percentage) in &safe.limit_percentage {
-                if *user == *send_to {
-                    continue;
-                }
-                if let Some(receiver_safe) = self.safes.get(send_to) {
-                    // TODO should return "limited or not"
-                    // edge should contain token balance and transfer limit (which can be unlimited)
-                    let limit = safe.trust_transfer_limit(receiver_safe, *percentage);
-                    if limit != U256::from(0) {
-                        edges.push(Edge {
-                            from: *user,
-                            to: *send_to,
-                            token: *user,
-                            capacity: limit,
-                        })
+            let own_balance = safe.balance(&safe.token_address);
+            if own_balance != U256::from(0) {
+                for (send_to, percentage) in &safe.limit_percentage {
+                    if *user == *send_to {
+                        continue;
+                    }
+                    if let Some(receiver_safe) = self.safes.get(send_to) {
+                        // The send limit is only the trust limit. The balance
+                        // is a separate constraint of the flow network.
+                        // Organizations can receive the full balance, as in the Hub.
+                        let limit = safe
+                            .trust_transfer_limit(receiver_safe, *percentage)
+                            .unwrap_or(own_balance);
+                        if limit != U256::from(0) {
+                            edges.push(Edge {
+                                from: *user,
+                                to: *send_to,
+                                token: *user,
+                                capacity: limit,
+                            })
+                        }
                     }
                 }
+                balances.push((*user, *user, own_balance));
             }
             // send tokens back to owner
             for (token, balance) in &safe.balances {
@@ -59,13 +163,74 @@ impl DB {
                             from: *user,
                             to: *owner,
                             token: *owner,
-                            // TODO capacity should be only limited by own balance.
                             capacity: *balance,
-                        })
+                        });
+                        balances.push((*user, *owner, *balance));
                     }
                 }
             }
         }
-        self.edges = EdgeDB::new(edges)
+        let token_addresses = self
+            .token_owner
+            .iter()
+            .map(|(token, owner)| (*owner, *token))
+            .collect();
+        self.edges = EdgeDB::new_with_token_addresses(edges, token_addresses);
+        for (holder, token_owner, balance) in balances {
+            self.edges.update_balance(holder, token_owner, balance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn send_limits_and_balances() {
+        let (a, b, c, org) = (
+            Address::from([1; 20]),
+            Address::from([2; 20]),
+            Address::from([3; 20]),
+            Address::from([4; 20]),
+        );
+        let safe = |balances: &[(Address, u128)], limits: &[(Address, u8)]| Safe {
+            token_address: Address::default(),
+            balances: balances
+                .iter()
+                .map(|(token, balance)| (*token, U256::from(*balance)))
+                .collect(),
+            limit_percentage: limits.iter().copied().collect(),
+            organization: false,
+        };
+        let mut safes = BTreeMap::from([
+            (a, safe(&[(a, 30)], &[(b, 50), (c, 50), (org, 50)])),
+            // trust limit 20
+            (b, safe(&[(b, 40)], &[])),
+            // trust limit 50, more than the balance of a
+            (c, safe(&[(c, 100)], &[])),
+            // organizations can receive all of a's balance
+            (org, safe(&[(b, 1000)], &[])),
+        ]);
+        safes.get_mut(&org).unwrap().organization = true;
+        for (address, safe) in &mut safes {
+            safe.token_address = *address;
+        }
+        let token_owner = safes.keys().map(|a| (*a, *a)).collect();
+        let db = DB::new(safes, token_owner);
+        let capacity = |to: Address| {
+            db.edges()
+                .outgoing(&a)
+                .iter()
+                .find(|e| e.to == to)
+                .map(|e| e.capacity)
+        };
+        assert_eq!(capacity(b), Some(U256::from(20)));
+        assert_eq!(capacity(c), Some(U256::from(50)));
+        assert_eq!(capacity(org), Some(U256::from(30)));
+        assert_eq!(db.edges().balance(&a, &a), Some(U256::from(30)));
+        // return to owner
+        assert_eq!(db.edges().outgoing(&org)[0].capacity, U256::from(1000));
+        assert_eq!(db.edges().balance(&org, &b), Some(U256::from(1000)));
     }
 }
@@ -0,0 +1,123 @@
+use serde::Deserialize;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use super::safes_json::JsonSafe;
+use super::source::SafeSource;
+
+const SAFE_FIELDS: &str = "id organization \
+    outgoing { limit limitPercentage canSendToAddress userAddress } \
+    incoming { limit limitPercentage canSendToAddress userAddress } \
+    balances { amount token { id owner { id } } }";
+
+/// Pages through the safes of a Circles subgraph in the order of their ids.
+///
+/// Failed requests are retried with exponential backoff, continuing
+/// after the last safe that was received.
+pub struct GraphQLSource {
+    url: String,
+    page_size: usize,
+    last_id: String,
+    max_retries: u32,
+    initial_backoff: Duration,
+    finished: bool,
+}
+
+impl GraphQLSource {
+    pub fn new(url: &str) -> GraphQLSource {
+        GraphQLSource {
+            url: url.to_string(),
+            page_size: 1000,
+            last_id: String::new(),
+            max_retries: 10,
+            initial_backoff: Duration::from_secs(1),
+            finished: false,
+        }
+    }
+
+    pub fn with_page_size(mut self, page_size: usize) -> GraphQLSource {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Sets the number of retries per page and the delay before the first retry,
+    /// which doubles with every further retry.
+    pub fn with_retries(mut self, max_retries: u32, initial_backoff: Duration) -> GraphQLSource {
+        self.max_retries = max_retries;
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Only fetches safes with an id larger than `id`, e.g. to resume an earlier download.
+    pub fn resume_after(mut self, id: &str) -> GraphQLSource {
+        self.last_id = id.to_string();
+        self
+    }
+
+    /// The id of the last safe that was received.
+    pub fn last_id(&self) -> &str {
+        &self.last_id
+    }
+
+    fn fetch_page(&self) -> Result<Vec<JsonSafe>, io::Error> {
+        let query = format!(
+            "{{ safes(orderBy: id, first: {}, where: {{ id_gt: \"{}\" }}) {{ {SAFE_FIELDS} }} }}",
+            self.page_size, self.last_id
+        );
+        let body = ureq::post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&serde_json::json!({ "query": query }).to_string())
+            .map_err(io::Error::other)?
+            .into_string()?;
+        let response: Response = serde_json::from_str(&body)?;
+        match (response.data, response.errors) {
+            (Some(data), None) => Ok(data.safes),
+            (_, errors) => Err(io::Error::other(format!(
+                "Error response from subgraph: {}",
+                errors.unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+impl SafeSource for GraphQLSource {
+    fn next_batch(&mut self) -> Result<Vec<JsonSafe>, io::Error> {
+        if self.finished {
+            return Ok(vec![]);
+        }
+        let mut backoff = self.initial_backoff;
+        let mut retries = 0;
+        let safes = loop {
+            match self.fetch_page() {
+                Ok(safes) => break safes,
+                Err(e) if retries < self.max_retries => {
+                    log::warn!("Error fetching safes after {}: {e}", self.last_id);
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    retries += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        match safes.last() {
+            Some(last) => {
+                log::info!("Fetched {} safes up to {}.", safes.len(), last.id);
+                self.last_id = last.id.clone();
+            }
+            None => self.finished = true,
+        }
+        Ok(safes)
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    data: Option<Data>,
+    errors: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Data {
+    safes: Vec<JsonSafe>,
+}
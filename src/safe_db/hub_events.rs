@@ -0,0 +1,418 @@
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+
+use crate::types::{Address, U256};
+
+use super::db::DB;
+
+/// `Signup(address indexed user, address token)`
+const SIGNUP: &str = "0x358ba8f768af134eb5af120e9a61dc1ef29b29f597f047b555fc3675064a0342";
+/// `OrganizationSignup(address indexed organization)`
+const ORGANIZATION_SIGNUP: &str =
+    "0xb0b94cff8b84fc67513b977d68a5cdd67550bd9b8d99a34b570e3367b7843786";
+/// `Trust(address indexed canSendTo, address indexed user, uint256 limit)`
+const TRUST: &str = "0xe60c754dd8ab0b1b5fccba257d6ebcd7d09e360ab7dd7a6e58198ca1f57cdcec";
+/// `HubTransfer(address indexed from, address indexed to, uint256 amount)`
+const HUB_TRANSFER: &str = "0x8451019aab65b4193860ef723cb0d56b475a26a72b7bfc55c1dbd6121015285a";
+/// ERC20 `Transfer(address indexed from, address indexed to, uint256 value)`
+const TRANSFER: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+/// An event emitted by the Circles Hub or one of its token contracts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HubEvent {
+    Signup {
+        user: Address,
+        token: Address,
+    },
+    OrganizationSignup {
+        organization: Address,
+    },
+    /// `user` can send their tokens to `can_send_to` up to `limit` percent.
+    Trust {
+        can_send_to: Address,
+        user: Address,
+        limit: u8,
+    },
+    /// Summary of a transitive transfer. The balance changes are
+    /// contained in the `Transfer` events of the token contracts.
+    HubTransfer {
+        from: Address,
+        to: Address,
+        amount: U256,
+    },
+    /// ERC20 transfer of the token contract `token`.
+    /// Mints and burns use the zero address.
+    Transfer {
+        token: Address,
+        from: Address,
+        to: Address,
+        amount: U256,
+    },
+}
+
+/// A log entry as returned by `eth_getLogs`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Log {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub block_number: String,
+    pub log_index: String,
+    #[serde(default)]
+    pub removed: bool,
+}
+
+/// The block number and the log index of a log, which define the order of the logs.
+pub type LogPosition = (u64, u64);
+
+impl Log {
+    pub fn position(&self) -> Result<LogPosition, String> {
+        Ok((
+            parse_quantity(&self.block_number)?,
+            parse_quantity(&self.log_index)?,
+        ))
+    }
+
+    /// Decodes the log, returning `None` if it is not a Hub or ERC20 event.
+    /// ERC20 events are returned regardless of whether the contract
+    /// is a Circles token.
+    pub fn decode(&self) -> Result<Option<HubEvent>, String> {
+        let topic = |i: usize| -> Result<&str, String> {
+            self.topics
+                .get(i)
+                .map(|t| t.as_str())
+                .ok_or_else(|| format!("Missing topic {i} in log: {self:?}"))
+        };
+        let word = |i: usize| -> Result<&str, String> {
+            let data = self.data.strip_prefix("0x").unwrap_or(&self.data);
+            data.get(64 * i..64 * (i + 1))
+                .ok_or_else(|| format!("Missing data word {i} in log: {self:?}"))
+        };
+        let event = match topic(0)?.to_lowercase().as_str() {
+            SIGNUP => HubEvent::Signup {
+                user: word_to_address(topic(1)?)?,
+                token: word_to_address(word(0)?)?,
+            },
+            ORGANIZATION_SIGNUP => HubEvent::OrganizationSignup {
+                organization: word_to_address(topic(1)?)?,
+            },
+            TRUST => HubEvent::Trust {
+                can_send_to: word_to_address(topic(1)?)?,
+                user: word_to_address(topic(2)?)?,
+                limit: word_to_percentage(word(0)?)?,
+            },
+            HUB_TRANSFER => HubEvent::HubTransfer {
+                from: word_to_address(topic(1)?)?,
+                to: word_to_address(topic(2)?)?,
+                amount: word_to_u256(word(0)?)?,
+            },
+            // ERC20 transfers have three topics, ERC721 transfers four.
+            TRANSFER if self.topics.len() == 3 => HubEvent::Transfer {
+                token: self.address.parse()?,
+                from: word_to_address(topic(1)?)?,
+                to: word_to_address(topic(2)?)?,
+                amount: word_to_u256(word(0)?)?,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+}
+
+pub fn import_from_hub_events(file: &str) -> Result<DB, io::Error> {
+    import_from_hub_events_from(BufReader::new(File::open(file)?))
+}
+
+/// Creates a safe database by replaying all Hub events from the beginning.
+pub fn import_from_hub_events_from(reader: impl Read) -> Result<DB, io::Error> {
+    let mut db = DB::default();
+    db.apply_hub_events(&read_hub_events_from(reader)?);
+    Ok(db)
+}
+
+/// Reads logs in the format returned by `eth_getLogs`, either as a plain list
+/// or as a complete JSON-RPC response, and returns the decoded events in block order.
+/// Logs of other events are skipped.
+pub fn read_hub_events_from(reader: impl Read) -> Result<Vec<(LogPosition, HubEvent)>, io::Error> {
+    let logs = match serde_json::from_reader(reader)? {
+        LogFile::Logs(logs) => logs,
+        LogFile::Response { result } => result,
+    };
+    decode_logs(logs).map_err(io::Error::other)
+}
+
+/// Decodes the logs and returns the events with their positions in block order,
+/// without duplicates. Fails for logs that were removed by a chain reorganization,
+/// because the events cannot be reverted.
+pub fn decode_logs(logs: Vec<Log>) -> Result<Vec<(LogPosition, HubEvent)>, String> {
+    let mut events = Vec::new();
+    for log in logs {
+        let position = log.position()?;
+        if log.removed {
+            return Err(format!(
+                "Log {} of block {} was removed, but removed logs cannot be reverted.",
+                position.1, position.0
+            ));
+        }
+        if let Some(event) = log.decode()? {
+            events.push((position, event));
+        }
+    }
+    events.sort_by_key(|(position, _)| *position);
+    events.dedup_by_key(|(position, _)| *position);
+    Ok(events)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LogFile {
+    Logs(Vec<Log>),
+    Response { result: Vec<Log> },
+}
+
+fn parse_quantity(value: &str) -> Result<u64, String> {
+    value
+        .strip_prefix("0x")
+        .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| format!("Invalid quantity: {value}"))
+}
+
+fn word_to_address(word: &str) -> Result<Address, String> {
+    let hex = word.strip_prefix("0x").unwrap_or(word);
+    if hex.len() != 64 || !hex[..24].bytes().all(|b| b == b'0') {
+        return Err(format!("Invalid address word: {word}"));
+    }
+    hex[24..].parse()
+}
+
+fn word_to_percentage(word: &str) -> Result<u8, String> {
+    let value = word_to_u256(word)?;
+    if value > U256::from(100) {
+        return Err(format!("Invalid percentage: {word}"));
+    }
+    Ok(u8::from_str_radix(&word[word.len() - 2..], 16).unwrap())
+}
+
+fn word_to_u256(word: &str) -> Result<U256, String> {
+    let hex = word.strip_prefix("0x").unwrap_or(word);
+    format!("0x{hex}").parse()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const A: &str = "11c7e86ff693e9032a0f41711b5581a04b26be2e";
+    const B: &str = "22cedde51198d1773590311e2a340dc06b24cb37";
+    const TOKEN_A: &str = "aa7edde51198d1773590311e2a340dc06b24cb37";
+    const TOKEN_B: &str = "bbc16ce62d26fd51582a646e2e30a3267b1e6d7e";
+    const HUB: &str = "0x29b9a7fbb8995b2423a71cc17cf9810798f6c543";
+
+    fn word(hex: &str) -> String {
+        format!("0x{hex:0>64}")
+    }
+
+    fn log(address: &str, topics: &[String], data: &str, block: u64, index: u64) -> String {
+        format!(
+            r#"{{"address": "{address}", "topics": [{}], "data": "{data}",
+            "blockNumber": "{block:#x}", "logIndex": "{index:#x}", "removed": false}}"#,
+            topics
+                .iter()
+                .map(|t| format!("\"{t}\""))
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+
+    fn logs() -> String {
+        let logs = [
+            // Deliberately out of order: the trust comes after the signups.
+            log(HUB, &[TRUST.into(), word(B), word(A)], &word("32"), 3, 0),
+            log(HUB, &[SIGNUP.into(), word(A)], &word(TOKEN_A), 1, 0),
+            log(HUB, &[SIGNUP.into(), word(B)], &word(TOKEN_B), 2, 0),
+            // mints
+            log(
+                &format!("0x{TOKEN_A}"),
+                &[TRANSFER.into(), word(""), word(A)],
+                &word("64"),
+                2,
+                1,
+            ),
+            log(
+                &format!("0x{TOKEN_B}"),
+                &[TRANSFER.into(), word(""), word(B)],
+                &word("c8"),
+                2,
+                2,
+            ),
+            // transfer of 0x14 A tokens from A to B
+            log(
+                &format!("0x{TOKEN_A}"),
+                &[TRANSFER.into(), word(A), word(B)],
+                &word("14"),
+                4,
+                0,
+            ),
+            log(
+                HUB,
+                &[HUB_TRANSFER.into(), word(A), word(B)],
+                &word("14"),
+                4,
+                1,
+            ),
+            // unrelated
+            log(HUB, &[word("1234")], "0x", 4, 2),
+        ];
+        format!(
+            r#"{{"jsonrpc": "2.0", "id": 1, "result": [{}]}}"#,
+            logs.join(",")
+        )
+    }
+
+    #[test]
+    fn decode() {
+        let events = read_hub_events_from(logs().as_bytes()).unwrap();
+        assert_eq!(events.len(), 7);
+        assert_eq!(
+            events[0].1,
+            HubEvent::Signup {
+                user: Address::from(A),
+                token: Address::from(TOKEN_A)
+            }
+        );
+        assert_eq!(
+            events[4].1,
+            HubEvent::Trust {
+                can_send_to: Address::from(B),
+                user: Address::from(A),
+                limit: 50
+            }
+        );
+        assert_eq!(
+            events[6].1,
+            HubEvent::HubTransfer {
+                from: Address::from(A),
+                to: Address::from(B),
+                amount: U256::from(20)
+            }
+        );
+    }
+
+    #[test]
+    fn replay() {
+        let (a, b) = (Address::from(A), Address::from(B));
+        let (token_a, token_b) = (Address::from(TOKEN_A), Address::from(TOKEN_B));
+        let db = import_from_hub_events_from(logs().as_bytes()).unwrap();
+        assert_eq!(db.safes()[&a].token_address, token_a);
+        assert_eq!(db.safes()[&a].balance(&token_a), U256::from(80));
+        assert_eq!(db.safes()[&b].balance(&token_a), U256::from(20));
+        assert_eq!(db.safes()[&b].balance(&token_b), U256::from(200));
+        assert_eq!(db.safes()[&a].limit_percentage[&b], 50);
+        assert_eq!(db.token_owner()[&token_b], b);
+    }
+
+    #[test]
+    fn mint_before_signup() {
+        let a = Address::from(A);
+        let token_a = Address::from(TOKEN_A);
+        // The Hub mints the signup bonus before it emits the Signup event.
+        let logs = format!(
+            "[{}, {}]",
+            log(
+                &format!("0x{TOKEN_A}"),
+                &[TRANSFER.into(), word(""), word(A)],
+                &word("64"),
+                1,
+                0
+            ),
+            log(HUB, &[SIGNUP.into(), word(A)], &word(TOKEN_A), 1, 1)
+        );
+        let db = import_from_hub_events_from(logs.as_bytes()).unwrap();
+        assert_eq!(db.safes()[&a].balance(&token_a), U256::from(100));
+    }
+
+    #[test]
+    fn incremental() {
+        let (a, b) = (Address::from(A), Address::from(B));
+        let token_a = Address::from(TOKEN_A);
+        let mut db = import_from_hub_events_from(logs().as_bytes()).unwrap();
+        let untrust = format!(
+            "[{}]",
+            log(HUB, &[TRUST.into(), word(B), word(A)], &word("0"), 5, 0)
+        );
+        let burn = format!(
+            "[{}]",
+            log(
+                &format!("0x{TOKEN_A}"),
+                &[TRANSFER.into(), word(B), word("")],
+                &word("14"),
+                5,
+                1
+            )
+        );
+        db.apply_hub_events(&read_hub_events_from(untrust.as_bytes()).unwrap());
+        db.apply_hub_events(&read_hub_events_from(burn.as_bytes()).unwrap());
+        assert!(db.safes()[&a].limit_percentage.is_empty());
+        assert_eq!(db.safes()[&b].balance(&token_a), U256::from(0));
+        assert!(db.edges().outgoing(&a).is_empty());
+        assert_eq!(db.last_event(), Some((5, 1)));
+    }
+
+    #[test]
+    fn already_applied() {
+        let (a, b) = (Address::from(A), Address::from(B));
+        let token_a = Address::from(TOKEN_A);
+        let mut db = import_from_hub_events_from(logs().as_bytes()).unwrap();
+        assert_eq!(db.last_event(), Some((4, 1)));
+        // A retry with an overlapping range only applies the new transfer.
+        let transfer = log(
+            &format!("0x{TOKEN_A}"),
+            &[TRANSFER.into(), word(A), word(B)],
+            &word("a"),
+            5,
+            0,
+        );
+        let retry = format!(
+            "[{}, {transfer}, {transfer}]",
+            log(
+                &format!("0x{TOKEN_A}"),
+                &[TRANSFER.into(), word(A), word(B)],
+                &word("14"),
+                4,
+                0,
+            )
+        );
+        let events = read_hub_events_from(retry.as_bytes()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(db.apply_hub_events(&events), 1);
+        assert_eq!(db.apply_hub_events(&events), 0);
+        assert_eq!(db.safes()[&a].balance(&token_a), U256::from(70));
+        assert_eq!(db.safes()[&b].balance(&token_a), U256::from(30));
+    }
+
+    #[test]
+    fn removed_log() {
+        let logs = format!(
+            "[{}]",
+            log(HUB, &[TRUST.into(), word(B), word(A)], &word("32"), 1, 0)
+                .replace(r#""removed": false"#, r#""removed": true"#)
+        );
+        assert!(read_hub_events_from(logs.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn invalid_log() {
+        let logs = format!(
+            "[{}]",
+            log(HUB, &[TRUST.into(), word(B)], &word("32"), 1, 0)
+        );
+        assert!(read_hub_events_from(logs.as_bytes()).is_err());
+        let logs = format!(
+            "[{}]",
+            log(HUB, &[TRUST.into(), word(B), word(A)], &word("65"), 1, 0)
+        );
+        assert!(read_hub_events_from(logs.as_bytes()).is_err());
+    }
+}
@@ -1,2 +1,7 @@
+pub mod avatar_db;
+pub mod avatars_json;
 pub mod db;
+pub mod graphql;
+pub mod hub_events;
 pub mod safes_json;
+pub mod source;
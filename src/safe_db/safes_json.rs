@@ -1,108 +1,359 @@
-use serde::Deserialize;
-use std::collections::BTreeMap;
-use std::fs::read_to_string;
+use serde::de::{DeserializeSeed, Deserializer, Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
 
-use crate::types::{Address, Safe};
+use crate::types::{Address, Safe, U256};
 
 use super::db::DB;
 
-pub fn import_from_safes_json(file: &str) -> DB {
-    let contents = read_to_string(file).unwrap();
-    let db: Safes = serde_json::from_str(&contents).unwrap();
+pub fn import_from_safes_json(file: &str) -> Result<DB, io::Error> {
+    import_from_safes_json_from(BufReader::new(File::open(file)?))
+}
 
-    let mut safes: BTreeMap<Address, Safe> = Default::default();
-    let mut token_owner: BTreeMap<Address, Address> = Default::default();
+/// Imports safes in the format returned by the subgraph.
+///
+/// The safes are processed one at a time while they are parsed, so the
+/// input is never fully held in memory. Unknown fields are ignored with
+/// a warning. Errors contain the line and column in the input.
+pub fn import_from_safes_json_from(reader: impl Read) -> Result<DB, io::Error> {
+    let mut builder = SafesBuilder::default();
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+    SafesSeed(&mut builder).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(builder.finish())
+}
 
-    for json_safe in &db.safes {
-        let address: Address = json_safe.id.into();
-        let mut s = Safe {
-            organization: json_safe.organization,
-            ..Default::default()
-        };
-        for balance in &json_safe.balances {
-            let token_address: Address = balance.token.id.into();
-            let owner: Address = balance.token.owner.id.into();
-            s.balances.insert(token_address, balance.amount.into());
-            if owner == address {
-                s.token_address = token_address;
-            }
-            token_owner.insert(token_address, owner);
+pub fn export_safes_to_json(db: &DB, file: &str) -> Result<(), io::Error> {
+    let mut writer = BufWriter::new(File::create(file)?);
+    export_safes_to_json_to(db, &mut writer)?;
+    writer.flush()
+}
+
+/// Writes the safes in the format returned by the subgraph.
+/// Each trust connection is listed once, in the `outgoing` list of the user.
+pub fn export_safes_to_json_to(db: &DB, mut writer: impl Write) -> Result<(), io::Error> {
+    // The block number is not retained by the database.
+    write!(writer, "{{\"blockNumber\":\"0\",\"safes\":[")?;
+    for (i, (address, safe)) in db.safes().iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
         }
-        safes.insert(address, s);
+        let outgoing = safe
+            .limit_percentage
+            .iter()
+            .map(|(send_to, percentage)| {
+                json!({
+                    "limit": null,
+                    "limitPercentage": percentage.to_string(),
+                    "canSendToAddress": send_to.to_string(),
+                    "userAddress": address.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let balances = safe
+            .balances
+            .iter()
+            .map(|(token, amount)| {
+                let owner = db.token_owner().get(token).unwrap_or(token);
+                json!({
+                    "amount": amount.to_decimal(),
+                    "token": { "id": token.to_string(), "owner": { "id": owner.to_string() } },
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_writer(
+            &mut writer,
+            &json!({
+                "id": address.to_string(),
+                "organization": safe.organization,
+                "outgoing": outgoing,
+                "incoming": [],
+                "balances": balances,
+            }),
+        )?;
     }
+    write!(writer, "]}}")
+}
+
+/// Accumulates safes into the data needed for the database.
+#[derive(Default)]
+pub(crate) struct SafesBuilder {
+    safes: BTreeMap<Address, Safe>,
+    token_owner: BTreeMap<Address, Address>,
+    ignored_fields: BTreeSet<String>,
+}
+
+impl SafesBuilder {
+    pub(crate) fn add_safe(&mut self, json_safe: JsonSafe) -> Result<(), String> {
+        self.warn_unknown_fields("safe", &json_safe.unknown_fields);
+        for balance in &json_safe.balances {
+            self.warn_unknown_fields("balance", &balance.unknown_fields);
+            self.warn_unknown_fields("token", &balance.token.unknown_fields);
+            self.warn_unknown_fields("owner", &balance.token.owner.unknown_fields);
+        }
+        let address: Address = json_safe.id.parse()?;
+        let safe = self.safes.entry(address).or_default();
+        safe.organization = json_safe.organization;
+        for balance in &json_safe.balances {
+            let token_address: Address = balance.token.id.parse()?;
+            let owner: Address = balance.token.owner.id.parse()?;
+            safe.balances
+                .insert(token_address, balance.amount.parse::<U256>()?);
+            self.token_owner.insert(token_address, owner);
+        }
 
-    for json_safe in db.safes {
         for connection in json_safe.outgoing.iter().chain(json_safe.incoming.iter()) {
-            let send_to: Address = connection.can_send_to_address.into();
-            let user: Address = connection.user_address.into();
-            let limit_percentage: u8 = connection.limit_percentage.parse().unwrap();
-            assert!(limit_percentage <= 100);
+            self.warn_unknown_fields("trust edge", &connection.unknown_fields);
+            let send_to: Address = connection.can_send_to_address.parse()?;
+            let user: Address = connection.user_address.parse()?;
+            let limit_percentage = connection
+                .limit_percentage
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= 100)
+                .ok_or_else(|| {
+                    format!("Invalid limit percentage: {}", connection.limit_percentage)
+                })?;
             if send_to != Address::default()
                 && user != Address::default()
                 && send_to != user
                 && limit_percentage > 0
             {
-                safes
-                    .get_mut(&user)
-                    .unwrap()
+                self.safes
+                    .entry(user)
+                    .or_default()
                     .limit_percentage
                     .insert(send_to, limit_percentage);
             }
         }
+        Ok(())
+    }
+
+    fn warn_unknown_fields(&mut self, context: &str, fields: &BTreeMap<String, IgnoredAny>) {
+        for field in fields.keys() {
+            let name = format!("{context}.{field}");
+            if !self.ignored_fields.contains(&name) {
+                log::warn!("Ignoring unknown field \"{field}\" in {context}.");
+                self.ignored_fields.insert(name);
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> DB {
+        for (token, owner) in &self.token_owner {
+            if let Some(safe) = self.safes.get_mut(owner) {
+                safe.token_address = *token;
+            }
+        }
+        DB::new(self.safes, self.token_owner)
     }
-    DB::new(safes, token_owner)
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
-#[serde(rename_all = "camelCase")]
-struct Safes<'a> {
-    #[allow(dead_code)]
-    block_number: &'a str,
-    safes: Vec<JsonSafe<'a>>,
+/// Deserializes the top-level object, passing the safes on to the builder.
+struct SafesSeed<'a>(&'a mut SafesBuilder);
+
+impl<'de, 'a> DeserializeSeed<'de> for SafesSeed<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
+impl<'de, 'a> Visitor<'de> for SafesSeed<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an object with a list of safes")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut found_safes = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "safes" => {
+                    map.next_value_seed(SafeListSeed(self.0))?;
+                    found_safes = true;
+                }
+                "blockNumber" => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+                _ => {
+                    self.0
+                        .warn_unknown_fields("top level", &[(key, IgnoredAny)].into());
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if found_safes {
+            Ok(())
+        } else {
+            Err(A::Error::missing_field("safes"))
+        }
+    }
+}
+
+struct SafeListSeed<'a>(&'a mut SafesBuilder);
+
+impl<'de, 'a> DeserializeSeed<'de> for SafeListSeed<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for SafeListSeed<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a list of safes")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(safe) = seq.next_element::<JsonSafe>()? {
+            self.0.add_safe(safe).map_err(A::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+/// A safe in the format returned by the subgraph.
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
-struct JsonSafe<'a> {
-    id: &'a str,
-    organization: bool,
-    outgoing: Vec<Edge<'a>>,
-    incoming: Vec<Edge<'a>>,
-    balances: Vec<Balance<'a>>,
+pub struct JsonSafe {
+    pub id: String,
+    pub organization: bool,
+    pub outgoing: Vec<Edge>,
+    pub incoming: Vec<Edge>,
+    pub balances: Vec<Balance>,
+    #[serde(flatten, skip_serializing)]
+    pub unknown_fields: BTreeMap<String, IgnoredAny>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
-struct Edge<'a> {
-    #[allow(dead_code)]
-    limit: Option<&'a str>,
-    limit_percentage: &'a str,
-    can_send_to_address: &'a str,
-    user_address: &'a str,
+pub struct Edge {
+    pub limit: Option<String>,
+    pub limit_percentage: String,
+    pub can_send_to_address: String,
+    pub user_address: String,
+    #[serde(flatten, skip_serializing)]
+    pub unknown_fields: BTreeMap<String, IgnoredAny>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
-struct Balance<'a> {
-    amount: &'a str,
-    token: Token<'a>,
+pub struct Balance {
+    pub amount: String,
+    pub token: Token,
+    #[serde(flatten, skip_serializing)]
+    pub unknown_fields: BTreeMap<String, IgnoredAny>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
-struct Token<'a> {
-    id: &'a str,
-    owner: Owner<'a>,
+pub struct Token {
+    pub id: String,
+    pub owner: Owner,
+    #[serde(flatten, skip_serializing)]
+    pub unknown_fields: BTreeMap<String, IgnoredAny>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
-struct Owner<'a> {
-    id: &'a str,
+pub struct Owner {
+    pub id: String,
+    #[serde(flatten, skip_serializing)]
+    pub unknown_fields: BTreeMap<String, IgnoredAny>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAFES: &str = r#"{
+    "blockNumber": "123",
+    "safes": [{
+        "id": "0x11c7e86ff693e9032a0f41711b5581a04b26be2e",
+        "organization": false,
+        "outgoing": [{
+            "limit": null,
+            "limitPercentage": "50",
+            "canSendToAddress": "0x22cedde51198d1773590311e2a340dc06b24cb37",
+            "userAddress": "0x11c7e86ff693e9032a0f41711b5581a04b26be2e"
+        }],
+        "incoming": [],
+        "balances": [{
+            "amount": "1000",
+            "token": {
+                "id": "0xaa7edde51198d1773590311e2a340dc06b24cb37",
+                "owner": { "id": "0x11c7e86ff693e9032a0f41711b5581a04b26be2e" }
+            }
+        }],
+        "newField": [1, 2, 3]
+    }, {
+        "id": "0x22cedde51198d1773590311e2a340dc06b24cb37",
+        "organization": true,
+        "outgoing": [],
+        "incoming": [],
+        "balances": []
+    }]
+}"#;
+
+    #[test]
+    fn import() {
+        let a = Address::from("0x11c7e86ff693e9032a0f41711b5581a04b26be2e");
+        let b = Address::from("0x22cedde51198d1773590311e2a340dc06b24cb37");
+        let token_a = Address::from("0xaa7edde51198d1773590311e2a340dc06b24cb37");
+        let db = import_from_safes_json_from(SAFES.as_bytes()).unwrap();
+        assert_eq!(db.safes().len(), 2);
+        assert_eq!(db.safes()[&a].token_address, token_a);
+        assert_eq!(db.safes()[&a].balance(&token_a), U256::from(1000));
+        assert_eq!(db.safes()[&a].limit_percentage[&b], 50);
+        assert!(db.safes()[&b].organization);
+        assert_eq!(db.token_owner()[&token_a], a);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let db = import_from_safes_json_from(SAFES.as_bytes()).unwrap();
+        let mut buffer = Vec::new();
+        export_safes_to_json_to(&db, &mut buffer).unwrap();
+        let read = import_from_safes_json_from(buffer.as_slice()).unwrap();
+        assert_eq!(format!("{:?}", read.safes()), format!("{:?}", db.safes()));
+        assert_eq!(read.token_owner(), db.token_owner());
+    }
+
+    #[test]
+    fn invalid_address() {
+        let input = SAFES.replace(
+            "0x22cedde51198d1773590311e2a340dc06b24cb37\",\n",
+            "0x22\",\n",
+        );
+        let error = import_from_safes_json_from(input.as_bytes())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("Invalid address: 0x22"), "{error}");
+        assert!(error.contains("line 21"), "{error}");
+    }
+
+    #[test]
+    fn syntax_error() {
+        let error = import_from_safes_json_from(&SAFES.as_bytes()[..100])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("line 4"), "{error}");
+    }
+
+    #[test]
+    fn missing_safes() {
+        assert!(import_from_safes_json_from(r#"{"blockNumber": "1"}"#.as_bytes()).is_err());
+    }
 }
@@ -0,0 +1,154 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use super::db::DB;
+use super::safes_json::{JsonSafe, SafesBuilder};
+
+/// A source of safes that delivers them in batches, for example
+/// page by page from a remote API.
+pub trait SafeSource {
+    /// Returns the next batch of safes, or an empty batch
+    /// if all safes have been delivered.
+    fn next_batch(&mut self) -> Result<Vec<JsonSafe>, io::Error>;
+}
+
+/// Reads all safes from the source and creates the database.
+pub fn import_from_source(source: &mut impl SafeSource) -> Result<DB, io::Error> {
+    let mut builder = SafesBuilder::default();
+    add_batches(&mut builder, source, |_| Ok(()))?;
+    Ok(builder.finish())
+}
+
+/// Like `import_from_source`, but appends every batch to the `checkpoint` file
+/// (one safe per line) before it is processed, so that an interrupted import can
+/// be resumed: The safes already in the file are read first, and the source is
+/// created by `source` with the id of the last of them ("" if there is none).
+pub fn import_from_source_with_checkpoint<S: SafeSource>(
+    checkpoint: &Path,
+    source: impl FnOnce(&str) -> S,
+) -> Result<DB, io::Error> {
+    let mut builder = SafesBuilder::default();
+    let mut last_id = String::new();
+    if checkpoint.exists() {
+        let mut complete = 0;
+        let mut reader = BufReader::new(File::open(checkpoint)?);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            // A line without a newline was interrupted while it was written.
+            if !line.ends_with('\n') {
+                break;
+            }
+            let safe: JsonSafe = serde_json::from_str(&line)?;
+            last_id = safe.id.clone();
+            builder.add_safe(safe).map_err(io::Error::other)?;
+            complete += line.len() as u64;
+            line.clear();
+        }
+        OpenOptions::new()
+            .write(true)
+            .open(checkpoint)?
+            .set_len(complete)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(checkpoint)?;
+    add_batches(&mut builder, &mut source(&last_id), |batch| {
+        let mut lines = vec![];
+        for safe in batch {
+            serde_json::to_writer(&mut lines, safe)?;
+            lines.push(b'\n');
+        }
+        file.write_all(&lines)?;
+        file.sync_data()
+    })?;
+    Ok(builder.finish())
+}
+
+fn add_batches(
+    builder: &mut SafesBuilder,
+    source: &mut impl SafeSource,
+    mut on_batch: impl FnMut(&[JsonSafe]) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    loop {
+        let batch = source.next_batch()?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        on_batch(&batch)?;
+        for safe in batch {
+            builder.add_safe(safe).map_err(io::Error::other)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    /// Delivers the safes with the given ids in batches of one
+    /// and fails after `fail_after` batches.
+    struct TestSource {
+        ids: Vec<String>,
+        fail_after: usize,
+    }
+
+    impl SafeSource for TestSource {
+        fn next_batch(&mut self) -> Result<Vec<JsonSafe>, io::Error> {
+            if self.fail_after == 0 {
+                return Err(io::Error::other("interrupted"));
+            }
+            self.fail_after -= 1;
+            if self.ids.is_empty() {
+                return Ok(vec![]);
+            }
+            let id = self.ids.remove(0);
+            let safe = format!(
+                r#"{{"id": "{id}", "organization": false, "outgoing": [], "incoming": [], "balances": []}}"#
+            );
+            Ok(vec![serde_json::from_str(&safe).unwrap()])
+        }
+    }
+
+    #[test]
+    fn resume_from_checkpoint() {
+        let ids = [
+            "0x11c7e86ff693e9032a0f41711b5581a04b26be2e",
+            "0x22cedde51198d1773590311e2a340dc06b24cb37",
+            "0x33cedde51198d1773590311e2a340dc06b24cb37",
+        ];
+        let source = |after: &str, fail_after| TestSource {
+            ids: ids
+                .iter()
+                .filter(|id| **id > after)
+                .map(|id| id.to_string())
+                .collect(),
+            fail_after,
+        };
+        let checkpoint =
+            env::temp_dir().join(format!("pathfinder_checkpoint_{}", std::process::id()));
+        let _ = fs::remove_file(&checkpoint);
+
+        assert!(import_from_source_with_checkpoint(&checkpoint, |after| source(after, 2)).is_err());
+        // An incomplete line is dropped.
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&checkpoint)
+            .unwrap()
+            .write_all(b"{\"id\": \"0x33")
+            .unwrap();
+        let mut resumed_after = None;
+        let db = import_from_source_with_checkpoint(&checkpoint, |after| {
+            resumed_after = Some(after.to_string());
+            source(after, 10)
+        })
+        .unwrap();
+        assert_eq!(resumed_after.as_deref(), Some(ids[1]));
+        assert_eq!(db.safes().len(), 3);
+        assert_eq!(fs::read_to_string(&checkpoint).unwrap().lines().count(), 3);
+        fs::remove_file(&checkpoint).unwrap();
+    }
+}
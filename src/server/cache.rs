@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::graph::{FlowOptions, MaxFlow};
+use crate::types::{Address, Edge, U256};
+
+/// The number of transfer results kept per snapshot.
+const RESULT_CACHE_SIZE: usize = 1000;
+/// The number of maximum flows kept per snapshot. They are larger than
+/// the results, since they contain all edges used by the flow.
+const MAX_FLOW_CACHE_SIZE: usize = 100;
+
+/// A map that keeps at most `capacity` entries and removes
+/// the least recently used entry if it is full.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    /// The keys by the time they were last used.
+    usage: BTreeMap<u64, K>,
+    time: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            entries: HashMap::new(),
+            usage: BTreeMap::new(),
+            time: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let (value, last_used) = self.entries.get_mut(key)?;
+        self.usage.remove(last_used);
+        self.time += 1;
+        *last_used = self.time;
+        self.usage.insert(self.time, key.clone());
+        Some(value.clone())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((_, last_used)) = self.entries.remove(&key) {
+            self.usage.remove(&last_used);
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.usage.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.time += 1;
+        self.usage.insert(self.time, key.clone());
+        self.entries.insert(key, (value, self.time));
+    }
+}
+
+/// Identifies a maximum flow, i.e. a transfer up to the requested amount.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub from: Address,
+    pub to: Address,
+    pub max_distance: Option<u64>,
+    /// The options in a canonical form, see `options_key`.
+    pub options: String,
+}
+
+impl FlowKey {
+    pub fn new(
+        from: Address,
+        to: Address,
+        max_distance: Option<u64>,
+        options: &FlowOptions,
+    ) -> FlowKey {
+        FlowKey {
+            from,
+            to,
+            max_distance,
+            options: options_key(options),
+        }
+    }
+}
+
+/// Identifies the result of a transfer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResultKey {
+    pub flow: FlowKey,
+    pub requested_flow: U256,
+    pub max_transfers: Option<u64>,
+}
+
+/// The results of the flow computations on one snapshot of the edges.
+pub struct FlowCache {
+    results: LruCache<ResultKey, (U256, Vec<Edge>)>,
+    max_flows: LruCache<FlowKey, MaxFlow>,
+}
+
+impl Default for FlowCache {
+    fn default() -> Self {
+        FlowCache {
+            results: LruCache::new(RESULT_CACHE_SIZE),
+            max_flows: LruCache::new(MAX_FLOW_CACHE_SIZE),
+        }
+    }
+}
+
+impl FlowCache {
+    pub fn result(&mut self, key: &ResultKey) -> Option<(U256, Vec<Edge>)> {
+        self.results.get(key)
+    }
+
+    pub fn insert_result(&mut self, key: ResultKey, result: (U256, Vec<Edge>)) {
+        self.results.insert(key, result);
+    }
+
+    pub fn max_flow(&mut self, key: &FlowKey) -> Option<MaxFlow> {
+        self.max_flows.get(key)
+    }
+
+    pub fn insert_max_flow(&mut self, key: FlowKey, max_flow: MaxFlow) {
+        self.max_flows.insert(key, max_flow);
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            results: self.results.len(),
+            maxFlows: self.max_flows.len(),
+        }
+    }
+}
+
+/// How often the caches were used, over all snapshots.
+#[derive(Debug, Default)]
+pub struct CacheMetrics {
+    /// Requests answered from the result cache.
+    pub hits: AtomicU64,
+    /// Requests that only differed in the amount from a cached maximum flow.
+    pub max_flow_hits: AtomicU64,
+    /// Requests that computed the maximum flow.
+    pub misses: AtomicU64,
+}
+
+impl CacheMetrics {
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            hits: self.hits.load(Ordering::Relaxed),
+            maxFlowHits: self.max_flow_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A representation of the options that is equal for equal options.
+fn options_key(options: &FlowOptions) -> String {
+    let sorted = |addresses: &mut dyn Iterator<Item = &Address>| {
+        let mut addresses = addresses.map(|a| a.to_string()).collect::<Vec<_>>();
+        addresses.sort();
+        addresses.join(",")
+    };
+    let optional = |addresses: &Option<HashSet<Address>>| match addresses {
+        Some(addresses) => format!("[{}]", sorted(&mut addresses.iter())),
+        None => "*".to_string(),
+    };
+    let mut weights = options
+        .token_weights
+        .iter()
+        .map(|(token, weight)| format!("{token}={weight}"))
+        .collect::<Vec<_>>();
+    weights.sort();
+    format!(
+        "{:?};{};{};{};{};{};{}",
+        options.as_of,
+        sorted(&mut options.exclude_tokens.iter()),
+        sorted(&mut options.exclude_addresses.iter()),
+        optional(&options.only_tokens),
+        optional(&options.only_intermediaries),
+        weights.join(","),
+        optional(&options.accepted_tokens),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lru() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(&1), Some("a"));
+        // 2 is the least recently used.
+        cache.insert(3, "c");
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&3), Some("c"));
+        cache.insert(3, "d");
+        cache.insert(4, "e");
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&3), Some("d"));
+    }
+
+    #[test]
+    fn equal_options() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let b = Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37");
+        let options = |tokens: [Address; 2]| FlowOptions {
+            exclude_tokens: tokens.into_iter().collect(),
+            ..Default::default()
+        };
+        assert_eq!(options_key(&options([a, b])), options_key(&options([b, a])));
+        assert_ne!(
+            options_key(&options([a, b])),
+            options_key(&FlowOptions {
+                only_tokens: Some([a, b].into_iter().collect()),
+                ..Default::default()
+            })
+        );
+        assert_ne!(
+            options_key(&FlowOptions::default()),
+            options_key(&FlowOptions {
+                only_tokens: Some(Default::default()),
+                ..Default::default()
+            })
+        );
+    }
+}
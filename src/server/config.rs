@@ -0,0 +1,339 @@
+use std::fmt::Display;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::LevelFilter;
+use serde::{Deserialize, Deserializer};
+
+use crate::logging::LogFormat;
+
+/// The settings of the server. They are read from a TOML file, then from
+/// environment variables `PATHFINDER_<NAME>` and then from command line flags
+/// `--<name>`, where later sources override earlier ones.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen_at: String,
+    pub queue_size: usize,
+    pub threads: u64,
+    /// The default timeout of the flow computations, zero disables it.
+    pub timeout_ms: u64,
+    /// A graph loaded at startup.
+    pub graph_file: Option<PathBuf>,
+    #[serde(deserialize_with = "parsed")]
+    pub graph_format: GraphFormat,
+    /// If set, `graph_file` is checked for changes in this interval and reloaded.
+    pub watch_interval_ms: Option<u64>,
+    /// Larger requests are rejected before they are read. Uploads of large graphs
+    /// in the `data` parameter of the `load_*` methods need a higher limit.
+    pub max_request_bytes: usize,
+    /// The defaults for requests that do not specify `max_distance` or `max_transfers`.
+    pub max_distance: Option<u64>,
+    pub max_transfers: Option<u64>,
+    /// The directories the `load_*` methods can read files from,
+    /// any file can be read if not set.
+    pub allowed_load_dirs: Option<Vec<PathBuf>>,
+    #[serde(deserialize_with = "parsed")]
+    pub log_level: LevelFilter,
+    #[serde(deserialize_with = "parsed")]
+    pub log_format: LogFormat,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen_at: "127.0.0.1:8080".to_string(),
+            queue_size: 10,
+            threads: 4,
+            timeout_ms: 60000,
+            graph_file: None,
+            graph_format: GraphFormat::SafesBinary,
+            watch_interval_ms: None,
+            max_request_bytes: 10_000_000,
+            max_distance: None,
+            max_transfers: None,
+            allowed_load_dirs: None,
+            log_level: LevelFilter::Info,
+            log_format: LogFormat::Text,
+        }
+    }
+}
+
+/// The settings that can be given as positional arguments, in order.
+const POSITIONAL: &[&str] = &["listen_at", "queue_size", "threads", "timeout_ms"];
+
+/// The formats a graph can be loaded from, named like the `load_*` methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    SafesBinary,
+    SafesJson,
+    EdgesBinary,
+    EdgesCsv,
+    HubEvents,
+    AvatarsJson,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "safes_binary" => Ok(GraphFormat::SafesBinary),
+            "safes_json" => Ok(GraphFormat::SafesJson),
+            "edges_binary" => Ok(GraphFormat::EdgesBinary),
+            "edges_csv" => Ok(GraphFormat::EdgesCsv),
+            "hub_events" => Ok(GraphFormat::HubEvents),
+            "avatars_json" => Ok(GraphFormat::AvatarsJson),
+            _ => Err(format!(
+                "Invalid graph format: {s}, expected safes_binary, safes_json, edges_binary, edges_csv, hub_events or avatars_json"
+            )),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration from the file given by `--config` or `PATHFINDER_CONFIG`,
+    /// the environment variables and the arguments (without the program name).
+    /// Positional arguments are read as listen address, queue size, threads and timeout.
+    pub fn load(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Config, String> {
+        let mut flags = vec![];
+        let mut positional = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                positional.push(arg);
+                continue;
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => (
+                    flag.to_string(),
+                    args.next()
+                        .ok_or_else(|| format!("Missing value for --{flag}"))?,
+                ),
+            };
+            flags.push((name.replace('-', "_"), value));
+        }
+        if positional.len() > POSITIONAL.len() {
+            return Err(format!(
+                "Unexpected argument: {}",
+                positional[POSITIONAL.len()]
+            ));
+        }
+
+        let file = match flags.iter().rev().find(|(name, _)| name == "config") {
+            Some((_, file)) => Some(file.clone()),
+            None => env("PATHFINDER_CONFIG"),
+        };
+        let mut config = match file {
+            Some(file) => {
+                let contents = fs::read_to_string(&file)
+                    .map_err(|e| format!("Could not read config file {file}: {e}"))?;
+                Config::from_toml(&contents).map_err(|e| format!("Error in {file}: {e}"))?
+            }
+            None => Config::default(),
+        };
+        for name in Config::names() {
+            if let Some(value) = env(&format!("PATHFINDER_{}", name.to_uppercase())) {
+                config
+                    .set(name, &value)
+                    .map_err(|e| format!("Invalid PATHFINDER_{}: {e}", name.to_uppercase()))?;
+            }
+        }
+        for (name, value) in POSITIONAL.iter().zip(positional) {
+            config
+                .set(name, &value)
+                .map_err(|e| format!("Invalid {name} argument: {e}"))?;
+        }
+        for (name, value) in flags.into_iter().filter(|(name, _)| name != "config") {
+            config
+                .set(&name, &value)
+                .map_err(|e| format!("Invalid --{}: {e}", name.replace('_', "-")))?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Config, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    /// The names of all settings.
+    pub fn names() -> [&'static str; 13] {
+        [
+            "listen_at",
+            "queue_size",
+            "threads",
+            "timeout_ms",
+            "graph_file",
+            "graph_format",
+            "watch_interval_ms",
+            "max_request_bytes",
+            "max_distance",
+            "max_transfers",
+            "allowed_load_dirs",
+            "log_level",
+            "log_format",
+        ]
+    }
+
+    /// Sets the setting `name` from its textual representation.
+    /// Directories in `allowed_load_dirs` are separated by commas.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "listen_at" => self.listen_at = value.to_string(),
+            "queue_size" => self.queue_size = parse(value)?,
+            "threads" => self.threads = parse(value)?,
+            "timeout_ms" => self.timeout_ms = parse(value)?,
+            "graph_file" => self.graph_file = Some(PathBuf::from(value)),
+            "graph_format" => self.graph_format = parse(value)?,
+            "watch_interval_ms" => self.watch_interval_ms = Some(parse(value)?),
+            "max_request_bytes" => self.max_request_bytes = parse(value)?,
+            "max_distance" => self.max_distance = Some(parse(value)?),
+            "max_transfers" => self.max_transfers = Some(parse(value)?),
+            "allowed_load_dirs" => {
+                self.allowed_load_dirs = Some(
+                    value
+                        .split(',')
+                        .filter(|dir| !dir.is_empty())
+                        .map(PathBuf::from)
+                        .collect(),
+                )
+            }
+            "log_level" => self.log_level = parse(value)?,
+            "log_format" => self.log_format = parse(value)?,
+            _ => {
+                return Err(format!(
+                    "Unknown setting {name}, expected one of {}",
+                    Config::names().join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the settings and resolves the allowed directories.
+    fn validate(&mut self) -> Result<(), String> {
+        if self.threads == 0 {
+            return Err("threads must be at least 1".to_string());
+        }
+        if self.watch_interval_ms.is_some() && self.graph_file.is_none() {
+            return Err("watch_interval_ms requires graph_file".to_string());
+        }
+        if self.watch_interval_ms == Some(0) {
+            return Err("watch_interval_ms must be at least 1".to_string());
+        }
+        if let Some(dirs) = &mut self.allowed_load_dirs {
+            for dir in dirs {
+                *dir = dir.canonicalize().map_err(|e| {
+                    format!(
+                        "Invalid directory {} in allowed_load_dirs: {e}",
+                        dir.display()
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        (self.timeout_ms > 0).then(|| Duration::from_millis(self.timeout_ms))
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    value.parse().map_err(|e| format!("{value}: {e}"))
+}
+
+/// Deserializes a string using `FromStr`.
+fn parsed<'de, D: Deserializer<'de>, T: FromStr>(deserializer: D) -> Result<T, D::Error>
+where
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_toml() {
+        let config = Config::from_toml(
+            r#"
+            listen_at = "0.0.0.0:80"
+            threads = 8
+            graph_file = "graph.db"
+            graph_format = "edges_binary"
+            max_distance = 4
+            log_level = "debug"
+            log_format = "json"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                listen_at: "0.0.0.0:80".to_string(),
+                threads: 8,
+                graph_file: Some(PathBuf::from("graph.db")),
+                graph_format: GraphFormat::EdgesBinary,
+                max_distance: Some(4),
+                log_level: LevelFilter::Debug,
+                log_format: LogFormat::Json,
+                ..Default::default()
+            }
+        );
+        let error = Config::from_toml("thread = 8").unwrap_err();
+        assert!(error.contains("unknown field `thread`"), "{error}");
+        let error = Config::from_toml("graph_format = \"csv\"").unwrap_err();
+        assert!(error.contains("Invalid graph format: csv"), "{error}");
+        let config = Config::from_toml("graph_format = \"safes_json\"").unwrap();
+        assert_eq!(config.graph_format, GraphFormat::SafesJson);
+    }
+
+    #[test]
+    fn precedence() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let env = |name: &str| match name {
+            "PATHFINDER_THREADS" => Some("2".to_string()),
+            "PATHFINDER_QUEUE_SIZE" => Some("20".to_string()),
+            _ => None,
+        };
+        let config = Config::load(
+            args(&["127.0.0.1:9000", "--threads", "3", "--max-transfers=5"]),
+            env,
+        )
+        .unwrap();
+        assert_eq!(config.listen_at, "127.0.0.1:9000");
+        assert_eq!(config.queue_size, 20);
+        assert_eq!(config.threads, 3);
+        assert_eq!(config.max_transfers, Some(5));
+
+        assert_eq!(
+            Config::load(args(&["--threads", "x"]), env).unwrap_err(),
+            "Invalid --threads: x: invalid digit found in string"
+        );
+        assert_eq!(
+            Config::load(args(&["--threads", "0"]), env).unwrap_err(),
+            "threads must be at least 1"
+        );
+        assert!(Config::load(args(&["--thread", "1"]), env)
+            .unwrap_err()
+            .starts_with("Invalid --thread: Unknown setting thread"));
+        assert_eq!(
+            Config::load(args(&["--config"]), env).unwrap_err(),
+            "Missing value for --config"
+        );
+    }
+}
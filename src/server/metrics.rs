@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Latency buckets in seconds.
+const DURATION_BUCKETS: &[f64] = &[0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0];
+/// Flow buckets in CRC (units of 10^18).
+const FLOW_BUCKETS: &[f64] = &[0.0, 1.0, 10.0, 100.0, 1000.0, 10000.0, 100000.0];
+const TRANSFER_STEP_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+/// The methods counted separately, all others are counted as "other".
+const METHODS: &[&str] = &[
+    "load_edges_binary",
+    "load_edges_csv",
+    "load_safes_binary",
+    "load_safes_json",
+    "load_hub_events",
+    "load_avatars_json",
+    "apply_hub_events",
+    "compute_transfer",
+    "compute_max_flow",
+    "is_reachable",
+    "diagnose_transfer",
+    "suggest_trusts",
+    "cache_stats",
+    "update_edges",
+];
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: &'static [f64],
+    /// The number of observations per bucket (not cumulative).
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Histogram {
+        Histogram {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = self.buckets.iter().position(|bound| value <= *bound) {
+            self.counts[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in self.buckets.iter().zip(&self.counts) {
+            cumulative += count;
+            writeln!(
+                out,
+                "{name}_bucket{{{labels}{separator}le=\"{bound}\"}} {cumulative}"
+            )
+            .expect("");
+        }
+        writeln!(
+            out,
+            "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {}",
+            self.count
+        )
+        .expect("");
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        writeln!(out, "{name}_sum{labels} {}", self.sum).expect("");
+        writeln!(out, "{name}_count{labels} {}", self.count).expect("");
+    }
+}
+
+/// The metrics of the server, rendered in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// The request latency by method.
+    requests: Mutex<BTreeMap<&'static str, Histogram>>,
+    /// The flow and the number of transfer steps of the results of `compute_transfer`.
+    transfers: Mutex<Option<(Histogram, Histogram)>>,
+    /// Connections waiting for a worker.
+    queue_depth: AtomicU64,
+    /// Connections rejected because the queue was full.
+    rejected: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_request(&self, method: &str, duration: Duration) {
+        let method = METHODS
+            .iter()
+            .find(|m| **m == method)
+            .copied()
+            .unwrap_or("other");
+        self.requests
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_insert_with(|| Histogram::new(DURATION_BUCKETS))
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Records a result of `compute_transfer`, with the flow in CRC.
+    pub fn record_transfer(&self, flow: f64, transfer_steps: usize) {
+        let mut transfers = self.transfers.lock().unwrap();
+        let (flows, steps) = transfers.get_or_insert_with(|| {
+            (
+                Histogram::new(FLOW_BUCKETS),
+                Histogram::new(TRANSFER_STEP_BUCKETS),
+            )
+        });
+        flows.observe(flow);
+        steps.observe(transfer_steps as f64);
+    }
+
+    pub fn enqueued(&self) {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dequeued(&self) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the metrics, together with gauges of the current state given
+    /// as (name, help, value).
+    pub fn to_prometheus(&self, gauges: &[(&str, &str, u64)]) -> String {
+        let mut out = String::new();
+        let header = |out: &mut String, name: &str, kind: &str, help: &str| {
+            writeln!(out, "# HELP {name} {help}").expect("");
+            writeln!(out, "# TYPE {name} {kind}").expect("");
+        };
+
+        let name = "pathfinder_request_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "Duration of requests by method.",
+        );
+        for (method, histogram) in self.requests.lock().unwrap().iter() {
+            histogram.write(&mut out, name, &format!("method=\"{method}\""));
+        }
+
+        let (flows, steps) = self.transfers.lock().unwrap().clone().unwrap_or_else(|| {
+            (
+                Histogram::new(FLOW_BUCKETS),
+                Histogram::new(TRANSFER_STEP_BUCKETS),
+            )
+        });
+        let name = "pathfinder_transfer_flow_crc";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "Flow of computed transfers in CRC.",
+        );
+        flows.write(&mut out, name, "");
+        let name = "pathfinder_transfer_steps";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "Number of transfer steps of computed transfers.",
+        );
+        steps.write(&mut out, name, "");
+
+        let name = "pathfinder_queue_depth";
+        header(&mut out, name, "gauge", "Connections waiting for a worker.");
+        writeln!(out, "{name} {}", self.queue_depth.load(Ordering::Relaxed)).expect("");
+        let name = "pathfinder_rejected_connections_total";
+        header(
+            &mut out,
+            name,
+            "counter",
+            "Connections rejected with 503 because the queue was full.",
+        );
+        writeln!(out, "{name} {}", self.rejected.load(Ordering::Relaxed)).expect("");
+
+        for (name, help, value) in gauges {
+            header(&mut out, name, "gauge", help);
+            writeln!(out, "{name} {value}").expect("");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prometheus() {
+        let metrics = Metrics::default();
+        metrics.record_request("compute_transfer", Duration::from_millis(20));
+        metrics.record_request("compute_transfer", Duration::from_secs(2));
+        metrics.record_request("no_such_method", Duration::from_millis(1));
+        metrics.record_transfer(12.5, 3);
+        metrics.enqueued();
+        metrics.rejected();
+        let out = metrics.to_prometheus(&[("pathfinder_edges", "Number of edges.", 42)]);
+        let lines = out.lines().collect::<Vec<_>>();
+        for expected in [
+            "# TYPE pathfinder_request_duration_seconds histogram",
+            "pathfinder_request_duration_seconds_bucket{method=\"compute_transfer\",le=\"0.01\"} 0",
+            "pathfinder_request_duration_seconds_bucket{method=\"compute_transfer\",le=\"0.1\"} 1",
+            "pathfinder_request_duration_seconds_bucket{method=\"compute_transfer\",le=\"+Inf\"} 2",
+            "pathfinder_request_duration_seconds_count{method=\"compute_transfer\"} 2",
+            "pathfinder_request_duration_seconds_count{method=\"other\"} 1",
+            "pathfinder_transfer_flow_crc_bucket{le=\"10\"} 0",
+            "pathfinder_transfer_flow_crc_bucket{le=\"100\"} 1",
+            "pathfinder_transfer_steps_sum 3",
+            "pathfinder_queue_depth 1",
+            "pathfinder_rejected_connections_total 1",
+            "# TYPE pathfinder_edges gauge",
+            "pathfinder_edges 42",
+        ] {
+            assert!(lines.contains(&expected), "Missing {expected} in:\n{out}");
+        }
+    }
+}
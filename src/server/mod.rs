@@ -0,0 +1,1065 @@
+mod cache;
+pub mod config;
+mod metrics;
+mod watcher;
+
+use crate::graph;
+use crate::graph::diagnosis::diagnose_flow;
+use crate::graph::flow_matrix::flow_matrix;
+use crate::graph::reachability::ReachabilityIndex;
+use crate::graph::FlowNetwork;
+use crate::io::{import_from_safes_binary_from, read_edges_binary_from, read_edges_csv_from};
+use crate::safe_db::avatars_json::import_from_avatars_json_from;
+use crate::safe_db::db::DB;
+use crate::safe_db::hub_events::{decode_logs, import_from_hub_events_from, Log};
+use crate::safe_db::safes_json::import_from_safes_json_from;
+use crate::server::cache::{CacheMetrics, FlowCache, FlowKey, ResultKey};
+use crate::server::config::{Config, GraphFormat};
+use crate::server::metrics::Metrics;
+use crate::types::edge::{EdgeDB, GraphModel};
+use crate::types::{Address, Edge, U256};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use json::JsonValue;
+use num_bigint::BigUint;
+use regex::Regex;
+use std::any::Any;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::TrySendError;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// An edge database and the indices built from it, which are replaced together.
+#[derive(Default)]
+struct Snapshot {
+    /// Incremented each time the snapshot is replaced.
+    version: u64,
+    edges: EdgeDB,
+    /// The flow network of `edges`, shared by all requests on the snapshot.
+    network: FlowNetwork,
+    reachability: ReachabilityIndex,
+    /// Transfers computed on this snapshot.
+    cache: Mutex<FlowCache>,
+}
+
+impl Snapshot {
+    fn new(edges: EdgeDB) -> Snapshot {
+        let network = FlowNetwork::new(&edges);
+        let reachability = ReachabilityIndex::new(&edges);
+        Snapshot {
+            version: 0,
+            edges,
+            network,
+            reachability,
+            cache: Mutex::new(FlowCache::default()),
+        }
+    }
+
+    /// The snapshot of `edges`, which differ from the edges of this snapshot
+    /// only in `changed_edges` and the balances of `changed_balances`.
+    fn updated(
+        &self,
+        edges: EdgeDB,
+        changed_edges: &[Edge],
+        changed_balances: &[(Address, Address)],
+    ) -> Snapshot {
+        let mut network = self.network.clone();
+        network.update(&edges, changed_edges, changed_balances);
+        let reachability =
+            self.reachability
+                .updated(&self.edges, &edges, changed_edges, changed_balances);
+        Snapshot {
+            version: 0,
+            edges,
+            network,
+            reachability,
+            cache: Mutex::new(FlowCache::default()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    snapshot: RwLock<Arc<Snapshot>>,
+    /// The safe database the edges were computed from, if they were
+    /// loaded from safes. It is kept to apply Hub events.
+    safes: Mutex<Option<DB>>,
+    cache_metrics: CacheMetrics,
+    metrics: Metrics,
+    config: Config,
+}
+
+struct JsonRpcRequest {
+    id: JsonValue,
+    method: String,
+    params: JsonValue,
+}
+
+struct InputValidationError(String);
+impl Error for InputValidationError {}
+
+impl Debug for InputValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error: {}", self.0)
+    }
+}
+impl Display for InputValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error: {}", self.0)
+    }
+}
+
+fn validate_and_parse_ethereum_address(address: &str) -> Result<Address, Box<dyn Error>> {
+    let re = Regex::new(r"^0x[0-9a-fA-F]{40}$").unwrap();
+    if re.is_match(address) {
+        Ok(Address::from(address))
+    } else {
+        Err(Box::new(InputValidationError(format!(
+            "Invalid Ethereum address: {}",
+            address
+        ))))
+    }
+}
+
+/// Parses an optional array of addresses.
+fn parse_address_set(value: &JsonValue) -> Result<Option<HashSet<Address>>, Box<dyn Error>> {
+    match value {
+        JsonValue::Null => Ok(None),
+        JsonValue::Array(addresses) => Ok(Some(
+            addresses
+                .iter()
+                .map(|a| validate_and_parse_ethereum_address(&a.to_string()))
+                .collect::<Result<_, _>>()?,
+        )),
+        _ => Err(Box::new(InputValidationError(format!(
+            "Expected an array of addresses, but got {value}"
+        )))),
+    }
+}
+
+/// Parses an optional non-negative integer parameter.
+fn parse_optional_u64(params: &JsonValue, name: &str) -> Result<Option<u64>, Box<dyn Error>> {
+    let value = &params[name];
+    if value.is_null() {
+        return Ok(None);
+    }
+    match value.as_u64() {
+        Some(value) => Ok(Some(value)),
+        None => Err(Box::new(InputValidationError(format!(
+            "Invalid {name}: Expected a non-negative integer, but got {value}"
+        )))),
+    }
+}
+
+/// Parses an optional object mapping tokens to integer weights.
+fn parse_token_weights(value: &JsonValue) -> Result<HashMap<Address, i64>, Box<dyn Error>> {
+    match value {
+        JsonValue::Null => Ok(HashMap::new()),
+        JsonValue::Object(weights) => weights
+            .iter()
+            .map(|(token, weight)| {
+                let weight = weight.as_i64().ok_or_else(|| {
+                    InputValidationError(format!("Invalid weight for token {token}: {weight}"))
+                })?;
+                Ok((validate_and_parse_ethereum_address(token)?, weight))
+            })
+            .collect(),
+        _ => Err(Box::new(InputValidationError(format!(
+            "Expected an object mapping tokens to weights, but got {value}"
+        )))),
+    }
+}
+
+/// Parses the optional restrictions of a transfer. Tokens can be given
+/// as token owners or as the token contract addresses known to `edges`.
+/// The deadline is set from `timeout_ms` or the configured timeout.
+fn parse_flow_options(
+    params: &JsonValue,
+    edges: &EdgeDB,
+    config: &Config,
+) -> Result<graph::FlowOptions, Box<dyn Error>> {
+    let timeout = match parse_optional_u64(params, "timeout_ms")? {
+        Some(timeout_ms) => Some(Duration::from_millis(timeout_ms)),
+        None => config.timeout(),
+    };
+    let mut options = graph::FlowOptions {
+        as_of: parse_optional_u64(params, "as_of")?,
+        exclude_tokens: parse_address_set(&params["exclude_tokens"])?.unwrap_or_default(),
+        exclude_addresses: parse_address_set(&params["exclude_addresses"])?.unwrap_or_default(),
+        only_tokens: parse_address_set(&params["only_tokens"])?,
+        only_intermediaries: parse_address_set(&params["only_intermediaries"])?,
+        token_weights: parse_token_weights(&params["token_weights"])?,
+        accepted_tokens: parse_address_set(&params["accepted_tokens"])?,
+        deadline: timeout.map(|timeout| Instant::now() + timeout),
+    };
+    options.resolve_token_addresses(edges);
+    Ok(options)
+}
+
+fn validate_and_parse_u256(value_str: &str) -> Result<U256, Box<dyn Error>> {
+    match BigUint::from_str(value_str) {
+        Ok(parsed_value) => {
+            if parsed_value > U256::MAX.into() {
+                Err(Box::new(InputValidationError(format!(
+                    "Value {} is too large. Maximum value is {}.",
+                    parsed_value,
+                    U256::MAX
+                ))))
+            } else {
+                Ok(U256::from_bigint_truncating(parsed_value))
+            }
+        }
+        Err(e) => Err(Box::new(InputValidationError(format!(
+            "Invalid value: {}. Couldn't parse value: {}",
+            value_str, e
+        )))),
+    }
+}
+
+/// Starts the server, loads the configured graph and reloads it when it changes.
+/// Returns only if the graph cannot be loaded or the server cannot listen.
+pub fn start_server(config: Config) -> Result<(), Box<dyn Error>> {
+    let state: Arc<State> = Arc::new(State {
+        config,
+        ..Default::default()
+    });
+    if let Some(file) = &state.config.graph_file {
+        load_graph_file(&state, file)?;
+        if let Some(interval_ms) = state.config.watch_interval_ms {
+            let s = state.clone();
+            let file = file.clone();
+            thread::spawn(move || {
+                watcher::watch_file(&file, Duration::from_millis(interval_ms), || {
+                    // Requests keep using the previous graph if the new one is invalid,
+                    // and the file keeps being watched even if loading it panics.
+                    match panic::catch_unwind(AssertUnwindSafe(|| load_graph_file(&s, &file))) {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => log::error!("{e}"),
+                        Err(payload) => log::error!(
+                            "Panic loading graph file {}: {}",
+                            file.display(),
+                            panic_message(payload.as_ref())
+                        ),
+                    }
+                })
+            });
+        }
+    }
+
+    let (sender, receiver) = mpsc::sync_channel(state.config.queue_size);
+    let protected_receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..state.config.threads {
+        let rec = protected_receiver.clone();
+        let s = state.clone();
+        thread::spawn(move || loop {
+            let socket = rec.lock().unwrap().recv().unwrap();
+            s.metrics.dequeued();
+            // A panic while handling one request must not take down the worker.
+            match panic::catch_unwind(AssertUnwindSafe(|| handle_connection(s.deref(), socket))) {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => log::error!("Error handling connection: {e}"),
+                Err(payload) => {
+                    log::error!(
+                        "Panic handling connection: {}",
+                        panic_message(payload.as_ref())
+                    )
+                }
+            }
+        });
+    }
+    let listen_at = &state.config.listen_at;
+    let listener = TcpListener::bind(listen_at)
+        .map_err(|e| format!("Could not listen on {listen_at}: {e}"))?;
+    log::info!("Listening on {listen_at}");
+    loop {
+        match listener.accept() {
+            Ok((socket, _)) => {
+                state.metrics.enqueued();
+                match sender.try_send(socket) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(mut socket)) => {
+                        state.metrics.dequeued();
+                        state.metrics.rejected();
+                        let _ = socket.write_all(b"HTTP/1.1 503 Service Unavailable\r\n\r\n");
+                    }
+                    Err(TrySendError::Disconnected(_)) => {
+                        panic!("Internal communication channel disconnected.");
+                    }
+                }
+            }
+            Err(e) => log::error!("Error accepting connection: {e}"),
+        }
+    }
+}
+
+fn handle_connection(state: &State, mut socket: TcpStream) -> Result<(), Box<dyn Error>> {
+    let (path, payload) = read_payload(&mut socket, state.config.max_request_bytes)?;
+    if path == "/metrics" {
+        let body = prometheus_metrics(state);
+        socket.write_all(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )?;
+        return Ok(());
+    }
+    let request = parse_request(payload)?;
+    let id = request.id.dump();
+    let method = request.method.clone();
+    let started = Instant::now();
+    let result = handle_request(state, request, socket);
+    let duration = started.elapsed();
+    state.metrics.record_request(&method, duration);
+    log::info!(
+        id = id.as_str(),
+        method = method.as_str(),
+        duration_ms = duration.as_millis() as u64;
+        "Handled request"
+    );
+    result
+}
+
+fn prometheus_metrics(state: &State) -> String {
+    let snapshot = state.snapshot.read().unwrap().clone();
+    state.metrics.to_prometheus(&[
+        (
+            "pathfinder_snapshot_version",
+            "Number of times the edges were replaced.",
+            snapshot.version,
+        ),
+        (
+            "pathfinder_edges",
+            "Number of edges in the current snapshot.",
+            snapshot.edges.edge_count() as u64,
+        ),
+    ])
+}
+
+fn handle_request(
+    state: &State,
+    request: JsonRpcRequest,
+    mut socket: TcpStream,
+) -> Result<(), Box<dyn Error>> {
+    match request.method.as_str() {
+        "load_edges_binary" => {
+            let response = match load_edges_binary(state, &request.params) {
+                Ok(len) => jsonrpc_response(request.id, len),
+                Err(e) => {
+                    jsonrpc_error_response(request.id, -32000, &format!("Error loading edges: {e}"))
+                }
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "load_edges_csv" => {
+            let response = match load_edges_csv(state, &request.params) {
+                Ok(len) => jsonrpc_response(request.id, len),
+                Err(e) => {
+                    jsonrpc_error_response(request.id, -32000, &format!("Error loading edges: {e}"))
+                }
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "load_safes_binary" => {
+            let response = match load_safes_binary(state, &request.params) {
+                Ok(len) => jsonrpc_response(request.id, len),
+                Err(e) => {
+                    jsonrpc_error_response(request.id, -32000, &format!("Error loading edges: {e}"))
+                }
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "load_safes_json" => {
+            let response = match load_safes_json(state, &request.params) {
+                Ok(len) => jsonrpc_response(request.id, len),
+                Err(e) => {
+                    jsonrpc_error_response(request.id, -32000, &format!("Error loading edges: {e}"))
+                }
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "load_hub_events" => {
+            let response = match load_hub_events(state, &request.params) {
+                Ok(len) => jsonrpc_response(request.id, len),
+                Err(e) => {
+                    jsonrpc_error_response(request.id, -32000, &format!("Error loading edges: {e}"))
+                }
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "load_avatars_json" => {
+            let response = match load_avatars_json(state, &request.params) {
+                Ok(len) => jsonrpc_response(request.id, len),
+                Err(e) => {
+                    jsonrpc_error_response(request.id, -32000, &format!("Error loading edges: {e}"))
+                }
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "apply_hub_events" => {
+            let response = match request.params {
+                JsonValue::Array(logs) => match apply_hub_events(state, logs) {
+                    Ok(len) => jsonrpc_response(request.id, len),
+                    Err(e) => jsonrpc_error_response(
+                        request.id,
+                        -32000,
+                        &format!("Error applying events: {e}"),
+                    ),
+                },
+                _ => {
+                    jsonrpc_error_response(request.id, -32602, "Invalid arguments: Expected array.")
+                }
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "compute_transfer" => {
+            let snapshot = state.snapshot.read().unwrap().clone();
+            compute_transfer(request, state, &snapshot, socket)?;
+        }
+        "compute_max_flow" => {
+            let snapshot = state.snapshot.read().unwrap().clone();
+            let response = match compute_max_flow(&request.params, &snapshot, &state.config) {
+                Ok(result) => jsonrpc_response(request.id, result),
+                Err(e) => jsonrpc_error_response(
+                    request.id,
+                    -32000,
+                    &format!("Error computing max flow: {e}"),
+                ),
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "is_reachable" => {
+            let snapshot = state.snapshot.read().unwrap().clone();
+            let response = match is_reachable(&request.params, &snapshot) {
+                Ok(result) => jsonrpc_response(request.id, result),
+                Err(e) => jsonrpc_error_response(
+                    request.id,
+                    -32000,
+                    &format!("Error checking reachability: {e}"),
+                ),
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "diagnose_transfer" => {
+            let snapshot = state.snapshot.read().unwrap().clone();
+            let response = match diagnose_transfer(&request.params, &snapshot, &state.config) {
+                Ok(result) => jsonrpc_response(request.id, result),
+                Err(e) => jsonrpc_error_response(
+                    request.id,
+                    -32000,
+                    &format!("Error diagnosing transfer: {e}"),
+                ),
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "suggest_trusts" => {
+            let snapshot = state.snapshot.read().unwrap().clone();
+            let response = match suggest_trusts(&request.params, &snapshot, &state.config) {
+                Ok(result) => jsonrpc_response(request.id, result),
+                Err(e) => jsonrpc_error_response(
+                    request.id,
+                    -32000,
+                    &format!("Error suggesting trusts: {e}"),
+                ),
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        "cache_stats" => {
+            let mut stats = state.cache_metrics.to_json();
+            let snapshot = state.snapshot.read().unwrap().clone();
+            stats["entries"] = snapshot.cache.lock().unwrap().to_json();
+            socket.write_all(jsonrpc_response(request.id, stats).as_bytes())?;
+        }
+        "update_edges" => {
+            let response = match request.params {
+                JsonValue::Array(updates) => match update_edges(state, updates) {
+                    Ok(len) => jsonrpc_response(request.id, len),
+                    Err(e) => jsonrpc_error_response(
+                        request.id,
+                        -32000,
+                        &format!("Error updating edges: {e}"),
+                    ),
+                },
+                _ => {
+                    jsonrpc_error_response(request.id, -32602, "Invalid arguments: Expected array.")
+                }
+            };
+            socket.write_all(response.as_bytes())?;
+        }
+        _ => socket
+            .write_all(jsonrpc_error_response(request.id, -32601, "Method not found").as_bytes())?,
+    };
+    Ok(())
+}
+
+/// Opens the graph data referenced by the request parameters:
+/// Either the base64-encoded contents in `data` or a local file named by `file`,
+/// which has to be in one of the allowed directories if they are configured.
+fn graph_data(params: &JsonValue, config: &Config) -> Result<Box<dyn BufRead>, Box<dyn Error>> {
+    if let Some(data) = params["data"].as_str() {
+        return Ok(Box::new(Cursor::new(BASE64.decode(data)?)));
+    }
+    let file = PathBuf::from(params["file"].to_string());
+    if let Some(dirs) = &config.allowed_load_dirs {
+        let path = file.canonicalize()?;
+        if !dirs.iter().any(|dir| path.starts_with(dir)) {
+            return Err(Box::new(InputValidationError(format!(
+                "{} is not in an allowed directory",
+                file.display()
+            ))));
+        }
+    }
+    Ok(Box::new(BufReader::new(File::open(file)?)))
+}
+
+/// The message of a panic caught by `catch_unwind`.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+fn load_edges_binary(state: &State, params: &JsonValue) -> Result<usize, Box<dyn Error>> {
+    load_graph(
+        state,
+        GraphFormat::EdgesBinary,
+        graph_data(params, &state.config)?,
+    )
+}
+
+fn load_edges_csv(state: &State, params: &JsonValue) -> Result<usize, Box<dyn Error>> {
+    load_graph(
+        state,
+        GraphFormat::EdgesCsv,
+        graph_data(params, &state.config)?,
+    )
+}
+
+fn load_safes_binary(state: &State, params: &JsonValue) -> Result<usize, Box<dyn Error>> {
+    load_graph(
+        state,
+        GraphFormat::SafesBinary,
+        graph_data(params, &state.config)?,
+    )
+}
+
+fn load_safes_json(state: &State, params: &JsonValue) -> Result<usize, Box<dyn Error>> {
+    load_graph(
+        state,
+        GraphFormat::SafesJson,
+        graph_data(params, &state.config)?,
+    )
+}
+
+fn load_hub_events(state: &State, params: &JsonValue) -> Result<usize, Box<dyn Error>> {
+    load_graph(
+        state,
+        GraphFormat::HubEvents,
+        graph_data(params, &state.config)?,
+    )
+}
+
+fn load_avatars_json(state: &State, params: &JsonValue) -> Result<usize, Box<dyn Error>> {
+    load_graph(
+        state,
+        GraphFormat::AvatarsJson,
+        graph_data(params, &state.config)?,
+    )
+}
+
+/// Loads a graph and replaces the edges. The safe database is kept for graphs
+/// loaded from safes or Hub events, so that Hub events can be applied to them.
+/// Hub events are not supported for Circles v2 graphs.
+fn load_graph(
+    state: &State,
+    format: GraphFormat,
+    data: Box<dyn BufRead>,
+) -> Result<usize, Box<dyn Error>> {
+    let (updated_edges, safes) = match format {
+        GraphFormat::EdgesBinary => (read_edges_binary_from(data)?, None),
+        GraphFormat::EdgesCsv => (read_edges_csv_from(data)?, None),
+        GraphFormat::SafesBinary => {
+            let safes = import_from_safes_binary_from(data)?;
+            (safes.edges().clone(), Some(safes))
+        }
+        GraphFormat::SafesJson => {
+            let safes = import_from_safes_json_from(data)?;
+            (safes.edges().clone(), Some(safes))
+        }
+        GraphFormat::HubEvents => {
+            let safes = import_from_hub_events_from(data)?;
+            (safes.edges().clone(), Some(safes))
+        }
+        GraphFormat::AvatarsJson => (import_from_avatars_json_from(data)?.edges().clone(), None),
+    };
+    let len = updated_edges.edge_count();
+    *state.safes.lock().unwrap() = safes;
+    replace_snapshot(state, Snapshot::new(updated_edges));
+    Ok(len)
+}
+
+/// Loads the configured graph file in the configured format.
+fn load_graph_file(state: &State, file: &Path) -> Result<(), Box<dyn Error>> {
+    let data = File::open(file)
+        .map_err(|e| format!("Could not open graph file {}: {e}", file.display()))?;
+    let len = load_graph(
+        state,
+        state.config.graph_format,
+        Box::new(BufReader::new(data)),
+    )
+    .map_err(|e| format!("Could not load graph file {}: {e}", file.display()))?;
+    log::info!("Loaded {len} edges from {}", file.display());
+    Ok(())
+}
+
+/// Replaces the snapshot by `snapshot` with the next version.
+fn replace_snapshot(state: &State, mut snapshot: Snapshot) {
+    let mut current = state.snapshot.write().unwrap();
+    snapshot.version = current.version + 1;
+    *current = Arc::new(snapshot);
+}
+
+/// Applies Hub events to the safe database and re-computes the edges.
+/// Requires the graph to be loaded from safes or Hub events.
+fn apply_hub_events(state: &State, logs: Vec<JsonValue>) -> Result<usize, Box<dyn Error>> {
+    let logs = logs
+        .into_iter()
+        .map(|log| serde_json::from_str::<Log>(&log.dump()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let events = decode_logs(logs).map_err(InputValidationError)?;
+    let mut safes = state.safes.lock().unwrap();
+    let safes = safes.as_mut().ok_or_else(|| {
+        InputValidationError(
+            "No safes loaded, Hub events can only be applied to safes that were not changed by update_edges."
+                .into(),
+        )
+    })?;
+    let applied = safes.apply_hub_events(&events);
+    if applied < events.len() {
+        log::info!(
+            "Skipped {} Hub events that were already applied",
+            events.len() - applied
+        );
+    }
+    let updated_edges = safes.edges().clone();
+    let len = updated_edges.edge_count();
+    replace_snapshot(state, Snapshot::new(updated_edges));
+    Ok(len)
+}
+
+fn compute_transfer(
+    request: JsonRpcRequest,
+    state: &State,
+    snapshot: &Snapshot,
+    mut socket: TcpStream,
+) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    socket.write_all(chunked_header().as_bytes())?;
+
+    let parsed_value_param = match request.params["value"].as_str() {
+        Some(value_str) => validate_and_parse_u256(value_str)?,
+        None => U256::MAX,
+    };
+
+    let from_address = validate_and_parse_ethereum_address(&request.params["from"].to_string())?;
+    let to_address = validate_and_parse_ethereum_address(&request.params["to"].to_string())?;
+
+    let edges = &snapshot.edges;
+    let options = parse_flow_options(&request.params, edges, &state.config)?;
+    // If nothing can be sent, there is no need to compute the flow.
+    let upper_bound =
+        snapshot
+            .reachability
+            .max_flow_upper_bound_as_of(&from_address, &to_address, options.as_of);
+
+    let max_distance = request.params["max_distance"]
+        .as_u64()
+        .or(state.config.max_distance);
+    let max_distances = if upper_bound == U256::from(0) {
+        vec![max_distance]
+    } else if request.params["iterative"].as_bool().unwrap_or_default() {
+        [1, 2]
+            .into_iter()
+            .filter(|d| max_distance.is_none_or(|max| *d < max))
+            .map(Some)
+            .chain([max_distance])
+            .collect()
+    } else {
+        vec![max_distance]
+    };
+
+    let max_transfers = request.params["max_transfers"]
+        .as_u64()
+        .or(state.config.max_transfers);
+    for (i, &max_distance) in max_distances.iter().enumerate() {
+        let last = i + 1 == max_distances.len();
+        let (flow, transfers, timed_out) = if upper_bound == U256::from(0) {
+            (U256::from(0), vec![], false)
+        } else {
+            cached_flow(
+                snapshot,
+                &state.cache_metrics,
+                &from_address,
+                &to_address,
+                parsed_value_param,
+                max_distance,
+                max_transfers,
+                &options,
+            )
+        };
+        log::info!(
+            id = request.id.dump(),
+            from = from_address.to_checksummed_hex(),
+            to = to_address.to_checksummed_hex(),
+            max_distance = max_distance,
+            flow = flow.to_decimal(),
+            timed_out = timed_out,
+            duration_ms = started.elapsed().as_millis() as u64;
+            "Computed flow"
+        );
+        if last || timed_out {
+            state
+                .metrics
+                .record_transfer(flow.to_f64() / 1e18, transfers.len());
+        }
+        let mut result = json::object! {
+            maxFlowValue: flow.to_decimal(),
+            final: last && !timed_out,
+            timedOut: timed_out,
+            transferSteps: transfers.iter().map(|e| json::object! {
+                from: e.from.to_checksummed_hex(),
+                to: e.to.to_checksummed_hex(),
+                token_owner: e.token.to_checksummed_hex(),
+                token: edges.token_address(&e.token).map(|t| t.to_checksummed_hex()),
+                value: e.capacity.to_decimal(),
+            }).collect::<Vec<_>>(),
+        };
+        if edges.model() == GraphModel::V2 {
+            result["flowMatrix"] = flow_matrix(&from_address, &to_address, &transfers)
+                .map_err(InputValidationError)?
+                .to_json();
+        }
+        socket.write_all(
+            chunked_response(&(jsonrpc_result(request.id.clone(), result) + "\r\n")).as_bytes(),
+        )?;
+        if timed_out {
+            break;
+        }
+    }
+    socket.write_all(chunked_close().as_bytes())?;
+    Ok(())
+}
+
+/// Computes a transfer like `compute_flow`, using the results cached in the snapshot.
+/// If only the amount differs from a previous request, the maximum flow is reused.
+/// Returns the flow, the transfers and whether the deadline passed. The results of
+/// computations that were stopped at the deadline are not cached.
+#[allow(clippy::too_many_arguments)]
+fn cached_flow(
+    snapshot: &Snapshot,
+    metrics: &CacheMetrics,
+    from: &Address,
+    to: &Address,
+    requested_flow: U256,
+    max_distance: Option<u64>,
+    max_transfers: Option<u64>,
+    options: &graph::FlowOptions,
+) -> (U256, Vec<Edge>, bool) {
+    let flow_key = FlowKey::new(*from, *to, max_distance, options);
+    let result_key = ResultKey {
+        flow: flow_key.clone(),
+        requested_flow,
+        max_transfers,
+    };
+    if let Some((flow, transfers)) = snapshot.cache.lock().unwrap().result(&result_key) {
+        metrics.hits.fetch_add(1, Ordering::Relaxed);
+        return (flow, transfers, false);
+    }
+    let cached_max_flow = snapshot.cache.lock().unwrap().max_flow(&flow_key);
+    let max_flow = match cached_max_flow {
+        Some(max_flow) => {
+            metrics.max_flow_hits.fetch_add(1, Ordering::Relaxed);
+            max_flow
+        }
+        None => {
+            metrics.misses.fetch_add(1, Ordering::Relaxed);
+            let max_flow =
+                snapshot
+                    .network
+                    .max_flow(from, to, &snapshot.edges, max_distance, options);
+            if !max_flow.timed_out() {
+                snapshot
+                    .cache
+                    .lock()
+                    .unwrap()
+                    .insert_max_flow(flow_key, max_flow.clone());
+            }
+            max_flow
+        }
+    };
+    let timed_out = max_flow.timed_out();
+    let result =
+        graph::transfers_from_max_flow(from, to, max_flow, requested_flow, max_transfers, options);
+    if !timed_out {
+        snapshot
+            .cache
+            .lock()
+            .unwrap()
+            .insert_result(result_key, result.clone());
+    }
+    (result.0, result.1, timed_out)
+}
+
+fn compute_max_flow(
+    params: &JsonValue,
+    snapshot: &Snapshot,
+    config: &Config,
+) -> Result<JsonValue, Box<dyn Error>> {
+    let from_address = validate_and_parse_ethereum_address(&params["from"].to_string())?;
+    let to_address = validate_and_parse_ethereum_address(&params["to"].to_string())?;
+    let value = match params["value"].as_str() {
+        Some(value_str) => validate_and_parse_u256(value_str)?,
+        None => U256::MAX,
+    };
+    let options = parse_flow_options(params, &snapshot.edges, config)?;
+    // The flow cannot exceed the upper bound, so we can stop once it is reached.
+    let value = min(
+        value,
+        snapshot
+            .reachability
+            .max_flow_upper_bound_as_of(&from_address, &to_address, options.as_of),
+    );
+    if value == U256::from(0) {
+        return Ok(json::object! { maxFlowValue: value.to_decimal(), timedOut: false });
+    }
+    let (flow, timed_out) = snapshot.network.compute_max_flow(
+        &from_address,
+        &to_address,
+        &snapshot.edges,
+        value,
+        params["max_distance"].as_u64().or(config.max_distance),
+        &options,
+    );
+    Ok(json::object! { maxFlowValue: flow.to_decimal(), timedOut: timed_out })
+}
+
+fn is_reachable(params: &JsonValue, snapshot: &Snapshot) -> Result<JsonValue, Box<dyn Error>> {
+    let from_address = validate_and_parse_ethereum_address(&params["from"].to_string())?;
+    let to_address = validate_and_parse_ethereum_address(&params["to"].to_string())?;
+    let index = &snapshot.reachability;
+    Ok(json::object! {
+        reachable: index.is_reachable(&from_address, &to_address),
+        maxFlowUpperBound: index.max_flow_upper_bound(&from_address, &to_address).to_decimal(),
+    })
+}
+
+fn diagnose_transfer(
+    params: &JsonValue,
+    snapshot: &Snapshot,
+    config: &Config,
+) -> Result<JsonValue, Box<dyn Error>> {
+    let edges = &snapshot.edges;
+    let from_address = validate_and_parse_ethereum_address(&params["from"].to_string())?;
+    let to_address = validate_and_parse_ethereum_address(&params["to"].to_string())?;
+    let options = parse_flow_options(params, edges, config)?;
+    let (flow, bottlenecks, timed_out) = diagnose_flow(&from_address, &to_address, edges, &options);
+    Ok(json::object! {
+        maxFlowValue: flow.to_decimal(),
+        bottlenecks: bottlenecks.iter().map(|b| b.to_json()).collect::<Vec<_>>(),
+        timedOut: timed_out,
+    })
+}
+
+fn suggest_trusts(
+    params: &JsonValue,
+    snapshot: &Snapshot,
+    config: &Config,
+) -> Result<JsonValue, Box<dyn Error>> {
+    let from_address = validate_and_parse_ethereum_address(&params["from"].to_string())?;
+    let to_address = validate_and_parse_ethereum_address(&params["to"].to_string())?;
+    let value = validate_and_parse_u256(&params["value"].to_string())?;
+    let options = parse_flow_options(params, &snapshot.edges, config)?;
+    let (suggestions, timed_out) = graph::suggestions::suggest_trusts_greedily(
+        &from_address,
+        &to_address,
+        &snapshot.edges,
+        &snapshot.network,
+        value,
+        &options,
+    );
+    Ok(json::object! {
+        trusts: suggestions.iter().map(|s| json::object! {
+            truster: s.truster.to_checksummed_hex(),
+            token_owner: s.token.to_checksummed_hex(),
+            maxFlowValue: s.flow.to_decimal(),
+        }).collect::<Vec<_>>(),
+        timedOut: timed_out,
+    })
+}
+
+fn update_edges(state: &State, updates: Vec<JsonValue>) -> Result<usize, Box<dyn Error>> {
+    if updates.is_empty() {
+        return Ok(state.snapshot.read().unwrap().edges.edge_count());
+    }
+
+    // The updated edges can no longer be derived from the safes, so applying
+    // Hub events afterwards would silently revert the updates.
+    let mut safes = state.safes.lock().unwrap();
+    *safes = None;
+    let snapshot = state.snapshot.read().unwrap().clone();
+    let mut updating_edges = snapshot.edges.clone();
+    let mut changed_edges = vec![];
+    let mut changed_balances = vec![];
+    for e in updates {
+        let from = Address::from(e["from"].to_string().as_str());
+        let token = Address::from(e["token_owner"].to_string().as_str());
+        if e.has_key("balance") {
+            // A balance of the sender instead of an edge.
+            let balance = U256::from(e["balance"].to_string().as_str());
+            updating_edges.update_balance(from, token, balance);
+            changed_balances.push((from, token));
+        } else {
+            let edge = Edge {
+                from,
+                to: Address::from(e["to"].to_string().as_str()),
+                token,
+                capacity: U256::from(e["capacity"].to_string().as_str()),
+            };
+            updating_edges.update(edge);
+            changed_edges.push(edge);
+        }
+    }
+    let len = updating_edges.edge_count();
+    // Only the parts of the flow network and the index affected by the updates are recomputed.
+    replace_snapshot(
+        state,
+        snapshot.updated(updating_edges, &changed_edges, &changed_balances),
+    );
+    Ok(len)
+}
+
+fn parse_request(payload: Vec<u8>) -> Result<JsonRpcRequest, Box<dyn Error>> {
+    let mut request = json::parse(&String::from_utf8(payload)?)?;
+    log::debug!("Request: {request}");
+    let id = request["id"].take();
+    let params = request["params"].take();
+    match request["method"].as_str() {
+        Some(method) => Ok(JsonRpcRequest {
+            id,
+            method: method.to_string(),
+            params,
+        }),
+        _ => Err(From::from("Invalid JSON-RPC request: {request}")),
+    }
+}
+
+/// Reads an HTTP request and returns its path and payload.
+/// Payloads larger than `max_bytes` are rejected.
+fn read_payload(
+    socket: &mut TcpStream,
+    max_bytes: usize,
+) -> Result<(String, Vec<u8>), Box<dyn Error>> {
+    let mut reader = BufReader::new(socket);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|target| target.split('?').next())
+        .unwrap_or("/")
+        .to_string();
+    let mut length = 0;
+    for result in reader.by_ref().lines() {
+        let l = result?;
+        if l.is_empty() {
+            break;
+        }
+
+        let header = "content-length: ";
+        if l.to_lowercase().starts_with(header) {
+            length = l[header.len()..].parse::<usize>()?;
+        }
+    }
+    if length > max_bytes {
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 413 Payload Too Large\r\n\r\n")?;
+        return Err(From::from(format!(
+            "Request of {length} bytes exceeds the limit of {max_bytes} bytes"
+        )));
+    }
+    let mut payload = vec![0u8; length];
+
+    reader.read_exact(payload.as_mut_slice())?;
+    Ok((path, payload))
+}
+
+fn jsonrpc_response(id: JsonValue, result: impl Into<json::JsonValue>) -> String {
+    let payload = jsonrpc_result(id, result);
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        payload.len(),
+        payload
+    )
+}
+
+fn jsonrpc_result(id: JsonValue, result: impl Into<json::JsonValue>) -> String {
+    json::object! {
+        jsonrpc: "2.0",
+        id: id,
+        result: result.into(),
+    }
+    .dump()
+}
+
+fn jsonrpc_error_response(id: JsonValue, code: i64, message: &str) -> String {
+    let payload = json::object! {
+        jsonrpc: "2.0",
+        id: id,
+        error: {
+            code: code,
+            message: message
+        }
+    }
+    .dump();
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        payload.len(),
+        payload
+    )
+}
+
+fn chunked_header() -> String {
+    "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_string()
+}
+
+fn chunked_response(data: &str) -> String {
+    if data.is_empty() {
+        String::new()
+    } else {
+        format!("{:x}\r\n{}\r\n", data.len(), data)
+    }
+}
+
+fn chunked_close() -> String {
+    "0\r\n\r\n".to_string()
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// What identifies a version of a file.
+type Signature = Option<(SystemTime, u64)>;
+
+fn signature(file: &Path) -> Signature {
+    let metadata = fs::metadata(file).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Calls `reload` whenever `file` changed, checking it every `interval`. A change
+/// is only reported after the file stayed the same for one more interval, so that
+/// it is not read while it is still being written. Missing files are ignored.
+pub fn watch_file(file: &Path, interval: Duration, mut reload: impl FnMut()) -> ! {
+    let mut loaded = signature(file);
+    let mut pending = None;
+    loop {
+        thread::sleep(interval);
+        let current = signature(file);
+        if current.is_none() || current == loaded {
+            pending = None;
+        } else if pending != Some(current) {
+            pending = Some(current);
+        } else {
+            reload();
+            loaded = current;
+            pending = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::sync::mpsc;
+
+    #[test]
+    fn reload_on_change() {
+        let file = env::temp_dir().join(format!("pathfinder_watch_{}.csv", std::process::id()));
+        fs::write(&file, "a").unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let watched = file.clone();
+        thread::spawn(move || {
+            watch_file(&watched, Duration::from_millis(10), || {
+                sender.send(()).unwrap();
+            })
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(receiver.try_recv().is_err());
+
+        fs::write(&file, "ab").unwrap();
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        thread::sleep(Duration::from_millis(50));
+        assert!(receiver.try_recv().is_err());
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 
 #[derive(Clone, Copy, Default, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Address([u8; 20]);
@@ -41,6 +42,20 @@ impl From<&str> for Address {
     }
 }
 
+impl FromStr for Address {
+    type Err = String;
+
+    /// Parses a hex address with `0x` prefix, returning an error instead of panicking.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix("0x").unwrap_or(s);
+        if hex.len() == 20 * 2 && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Ok(Address::from(hex))
+        } else {
+            Err(format!("Invalid address: {s}"))
+        }
+    }
+}
+
 impl Display for Address {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(f, "0x")?;
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+use super::{Address, U256};
+
+/// The kind of an avatar registered at the Circles v2 Hub.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AvatarKind {
+    #[default]
+    Human,
+    /// Organizations do not have their own token.
+    Organization,
+    /// Groups mint their token against collateral,
+    /// subject to the rules of the mint policy contract.
+    Group { mint_policy: Address },
+}
+
+/// An avatar of the Circles v2 Hub.
+///
+/// ERC-1155 token ids are the addresses of the avatars that issued the tokens,
+/// so tokens are identified by the avatar address.
+#[derive(Default, Debug)]
+pub struct Avatar {
+    pub kind: AvatarKind,
+    /// Balances by token id.
+    pub balances: BTreeMap<Address, U256>,
+    /// Avatars whose tokens this avatar accepts, with the
+    /// expiry time of the trust (unix timestamp, inclusive).
+    pub trusts: BTreeMap<Address, u64>,
+}
+
+impl Avatar {
+    pub fn balance(&self, token: &Address) -> U256 {
+        *self.balances.get(token).unwrap_or(&U256::from(0))
+    }
+
+    /// @returns true if the avatar accepts tokens of `avatar` at time `timestamp`.
+    pub fn trusts_at(&self, avatar: &Address, timestamp: u64) -> bool {
+        self.trusts
+            .get(avatar)
+            .is_some_and(|expiry| *expiry >= timestamp)
+    }
+
+    pub fn is_group(&self) -> bool {
+        matches!(self.kind, AvatarKind::Group { .. })
+    }
+
+    pub fn has_token(&self) -> bool {
+        self.kind != AvatarKind::Organization
+    }
+}
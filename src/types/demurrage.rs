@@ -0,0 +1,116 @@
+use num_bigint::BigUint;
+
+use super::U256;
+
+/// The start of day zero of the Circles v2 Hub on Gnosis Chain.
+pub const INFLATION_DAY_ZERO: u64 = 1602720000;
+const DAY: u64 = 24 * 60 * 60;
+/// The daily demurrage factor (7% per year) as a 64.64 fixed point number,
+/// as used by the Hub.
+const GAMMA_64X64: u128 = 18443079296116538654;
+
+/// @returns the demurrage day of a unix timestamp.
+pub fn day(timestamp: u64) -> u64 {
+    timestamp.saturating_sub(INFLATION_DAY_ZERO) / DAY
+}
+
+/// Converts a static (inflationary) amount to the demurraged
+/// amount at `timestamp`, i.e. the amount a balance shows at that time.
+pub fn to_demurraged(inflationary: U256, timestamp: u64) -> U256 {
+    Demurrage::between(INFLATION_DAY_ZERO, timestamp).apply(inflationary)
+}
+
+/// Converts a demurraged amount at `timestamp` to the static (inflationary) amount.
+pub fn to_inflationary(demurraged: U256, timestamp: u64) -> U256 {
+    Demurrage::between(timestamp, INFLATION_DAY_ZERO).apply(demurraged)
+}
+
+/// Converts demurraged amounts from one point in time to another.
+///
+/// The results are rounded down and can differ from the results of the
+/// Hub in the last digits, which is negligible for token amounts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Demurrage {
+    /// 64.64 fixed point factor
+    factor: BigUint,
+}
+
+impl Demurrage {
+    /// @returns the conversion of amounts demurraged at `from` to amounts demurraged at `to`.
+    /// If `to` is before `from`, the amounts increase.
+    pub fn between(from: u64, to: u64) -> Demurrage {
+        let (from, to) = (day(from), day(to));
+        let factor = if to >= from {
+            gamma_pow(to - from)
+        } else {
+            (BigUint::from(1u32) << 128) / gamma_pow(from - to)
+        };
+        Demurrage { factor }
+    }
+
+    pub fn apply(&self, amount: U256) -> U256 {
+        let result: BigUint = (BigUint::from(amount) * &self.factor) >> 64;
+        if result.bits() > 256 {
+            U256::MAX
+        } else {
+            U256::from_bigint_truncating(result)
+        }
+    }
+}
+
+/// @returns gamma^n as a 64.64 fixed point number.
+fn gamma_pow(mut n: u64) -> BigUint {
+    let mut result = BigUint::from(1u32) << 64;
+    let mut base = BigUint::from(GAMMA_64X64);
+    while n > 0 {
+        if n & 1 == 1 {
+            result = (result * &base) >> 64;
+        }
+        base = (&base * &base) >> 64;
+        n >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ONE: U256 = U256::new(0, 1_000_000_000_000_000_000);
+
+    #[test]
+    fn days() {
+        assert_eq!(day(0), 0);
+        assert_eq!(day(INFLATION_DAY_ZERO + DAY - 1), 0);
+        assert_eq!(day(INFLATION_DAY_ZERO + 2 * DAY + 5), 2);
+    }
+
+    #[test]
+    fn seven_percent_per_year() {
+        let after_a_year = to_demurraged(U256::from(100) * ONE, INFLATION_DAY_ZERO + 365 * DAY);
+        // 0.93^(365 / 365.25)
+        assert!(after_a_year > U256::from(9300) * ONE / U256::from(100));
+        assert!(after_a_year < U256::from(9301) * ONE / U256::from(100));
+    }
+
+    #[test]
+    fn roundtrip() {
+        let timestamp = INFLATION_DAY_ZERO + 1234 * DAY;
+        let amount = U256::from(123456) * ONE;
+        let converted = to_inflationary(to_demurraged(amount, timestamp), timestamp);
+        assert!(converted <= amount);
+        // The relative error is negligible.
+        let epsilon = amount / U256::from(1_000_000_000_000_000);
+        assert!(amount - converted < epsilon);
+
+        let later = Demurrage::between(timestamp, timestamp + 10 * DAY).apply(amount);
+        assert!(later < amount);
+        assert_eq!(
+            Demurrage::between(timestamp, timestamp + 10).apply(amount),
+            amount
+        );
+        let back = Demurrage::between(timestamp + 10 * DAY, timestamp).apply(later);
+        assert!(back <= amount);
+        assert!(amount - back < epsilon);
+    }
+}
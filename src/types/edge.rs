@@ -8,6 +8,8 @@ pub struct Edge {
     pub from: Address,
     pub to: Address,
     pub token: Address,
+    /// The send limit, i.e. how many `token` tokens `from` can send to `to`
+    /// regardless of its balance. For flows, this is the transferred amount.
     pub capacity: U256,
 }
 
@@ -17,21 +19,61 @@ pub fn eq_up_to_capacity(e1: &Edge, e2: &Edge) -> bool {
     e1.from == e2.from && e1.to == e2.to && e1.token == e2.token
 }
 
+/// The version of the Circles Hub whose rules a graph follows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GraphModel {
+    /// Hub v1: ERC20 tokens, trust limited by percentages.
+    #[default]
+    V1,
+    /// Hub v2: ERC-1155 tokens and binary trust. A receiver
+    /// accepts any amount of the tokens it trusts.
+    V2,
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct EdgeDB {
     edges: Vec<Edge>,
     outgoing: HashMap<Address, Vec<usize>>,
     incoming: HashMap<Address, Vec<usize>>,
+    /// Token contract address by token owner, if known.
+    /// The `token` field of the edges is always the token owner.
+    token_addresses: HashMap<Address, Address>,
+    /// The inverse of `token_addresses`.
+    token_owners: HashMap<Address, Address>,
+    /// Balances by holder and token owner, if known.
+    /// If the balance of an edge's sender is not known, it is
+    /// approximated by the largest send limit of the sender in that token.
+    balances: HashMap<(Address, Address), U256>,
+    model: GraphModel,
+    /// The time the balances and capacities refer to (unix timestamp),
+    /// if they are demurraged (v2).
+    timestamp: Option<u64>,
 }
 
 impl EdgeDB {
     pub fn new(edges: Vec<Edge>) -> EdgeDB {
+        EdgeDB::new_with_token_addresses(edges, HashMap::new())
+    }
+
+    pub fn new_with_token_addresses(
+        edges: Vec<Edge>,
+        token_addresses: HashMap<Address, Address>,
+    ) -> EdgeDB {
         let outgoing = outgoing_index(&edges);
         let incoming = incoming_index(&edges);
+        let token_owners = token_addresses
+            .iter()
+            .map(|(token_owner, token)| (*token, *token_owner))
+            .collect();
         EdgeDB {
             edges,
             outgoing,
             incoming,
+            token_addresses,
+            token_owners,
+            balances: HashMap::new(),
+            model: GraphModel::V1,
+            timestamp: None,
         }
     }
 
@@ -43,6 +85,56 @@ impl EdgeDB {
         &self.edges
     }
 
+    pub fn token_addresses(&self) -> &HashMap<Address, Address> {
+        &self.token_addresses
+    }
+
+    /// Returns the address of the token contract owned by `token_owner`, if known.
+    pub fn token_address(&self, token_owner: &Address) -> Option<&Address> {
+        self.token_addresses.get(token_owner)
+    }
+
+    /// Returns the owner of the token contract at `token`, if known.
+    pub fn token_owner(&self, token: &Address) -> Option<&Address> {
+        self.token_owners.get(token)
+    }
+
+    /// Mint edges only exist in v2 graphs. They have the group as sender and
+    /// receiver and the collateral as token, and describe how many group tokens
+    /// can be minted from the collateral, instead of a transfer.
+    pub fn is_mint(&self, edge: &Edge) -> bool {
+        self.model == GraphModel::V2 && edge.from == edge.to
+    }
+
+    pub fn model(&self) -> GraphModel {
+        self.model
+    }
+
+    pub fn set_model(&mut self, model: GraphModel) {
+        self.model = model;
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: Option<u64>) {
+        self.timestamp = timestamp;
+    }
+
+    pub fn balances(&self) -> &HashMap<(Address, Address), U256> {
+        &self.balances
+    }
+
+    /// Returns the balance of `holder` in tokens of `token_owner`, if known.
+    pub fn balance(&self, holder: &Address, token_owner: &Address) -> Option<U256> {
+        self.balances.get(&(*holder, *token_owner)).copied()
+    }
+
+    pub fn update_balance(&mut self, holder: Address, token_owner: Address, balance: U256) {
+        self.balances.insert((holder, token_owner), balance);
+    }
+
     pub fn update(&mut self, update: Edge) {
         match self.index_of(&update) {
             Some(i) => self.edges[i].capacity = update.capacity,
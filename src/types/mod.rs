@@ -1,10 +1,13 @@
 pub mod address;
+pub mod avatar;
+pub mod demurrage;
 pub mod edge;
 pub mod safe;
 pub mod token;
 pub mod u256;
 
 pub use address::Address;
+pub use avatar::{Avatar, AvatarKind};
 pub use edge::Edge;
 pub use safe::Safe;
 pub use token::Token;
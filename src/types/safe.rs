@@ -1,4 +1,4 @@
-use std::{cmp::min, collections::BTreeMap};
+use std::collections::BTreeMap;
 
 use super::{Address, U256};
 
@@ -17,30 +17,87 @@ impl Safe {
     pub fn balance(&self, token: &Address) -> U256 {
         *self.balances.get(token).unwrap_or(&U256::from(0))
     }
-    /// @returns how much of their own tokens a user can send to receiver.
-    pub fn trust_transfer_limit(&self, receiver: &Safe, trust_percentage: u8) -> U256 {
+    /// @returns how much of their own tokens a user can send to receiver
+    /// according to the trust limit (`Hub.checkSendLimit`), or None if
+    /// the amount is not limited by trust (the receiver is an organization).
+    /// This does not take the balance of the sender into account,
+    /// which is a separate constraint.
+    pub fn trust_transfer_limit(&self, receiver: &Safe, trust_percentage: u8) -> Option<U256> {
         if receiver.organization {
-            // TODO treat this as "return to owner"
-            // i.e. limited / only constrained by the balance edge.
-            self.balance(&self.token_address)
-        } else {
-            let receiver_balance = receiver.balance(&self.token_address);
+            return None;
+        }
+        let trust_percentage = trust_percentage.min(100);
+        let receiver_balance = receiver.balance(&self.token_address);
 
-            let amount = (receiver.balance(&receiver.token_address)
-                * U256::from(trust_percentage as u128))
-                / U256::from(100);
+        let amount = (receiver.balance(&receiver.token_address)
+            * U256::from(trust_percentage as u128))
+            / U256::from(100);
+        if amount < receiver_balance {
+            Some(U256::from(0))
+        } else {
             let scaled_receiver_balance =
                 receiver_balance * U256::from((100 - trust_percentage) as u128) / U256::from(100);
-            if amount < receiver_balance {
-                U256::from(0)
-            } else {
-                // TODO it should not be "min" - the second constraint
-                // is set by the balance edge.
-                min(
-                    amount - scaled_receiver_balance,
-                    self.balance(&self.token_address),
-                )
-            }
+            Some(amount - scaled_receiver_balance)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn safe(token: Address, balances: &[(Address, u128)]) -> Safe {
+        Safe {
+            token_address: token,
+            balances: balances
+                .iter()
+                .map(|(token, balance)| (*token, U256::from(*balance)))
+                .collect(),
+            ..Default::default()
         }
     }
+
+    #[test]
+    fn trust_transfer_limit() {
+        let (token_a, token_b) = (Address::from([1; 20]), Address::from([2; 20]));
+        let sender = safe(token_a, &[(token_a, 1000)]);
+        // The limit does not depend on the balance of the sender.
+        assert_eq!(
+            safe(token_a, &[]).trust_transfer_limit(&safe(token_b, &[(token_b, 100)]), 50),
+            Some(U256::from(50))
+        );
+        assert_eq!(
+            sender.trust_transfer_limit(&safe(token_b, &[(token_b, 100)]), 50),
+            Some(U256::from(50))
+        );
+        // max = 100 * 50% = 50, scaled receiver balance = 20 * 50% = 10
+        assert_eq!(
+            sender.trust_transfer_limit(&safe(token_b, &[(token_b, 100), (token_a, 20)]), 50),
+            Some(U256::from(40))
+        );
+        // The receiver already holds more than max.
+        assert_eq!(
+            sender.trust_transfer_limit(&safe(token_b, &[(token_b, 100), (token_a, 60)]), 50),
+            Some(U256::from(0))
+        );
+        assert_eq!(
+            sender.trust_transfer_limit(&safe(token_b, &[(token_b, 100), (token_a, 30)]), 100),
+            Some(U256::from(100))
+        );
+        assert_eq!(
+            sender.trust_transfer_limit(&safe(token_b, &[(token_b, 100)]), 0),
+            Some(U256::from(0))
+        );
+    }
+
+    #[test]
+    fn trust_transfer_limit_organization() {
+        let (token_a, token_b) = (Address::from([1; 20]), Address::from([2; 20]));
+        let sender = safe(token_a, &[(token_a, 1000)]);
+        let organization = Safe {
+            organization: true,
+            ..safe(token_b, &[(token_a, 5000)])
+        };
+        assert_eq!(sender.trust_transfer_limit(&organization, 50), None);
+    }
 }
@@ -4,6 +4,7 @@ use std::fmt::Formatter;
 use std::ops::Div;
 use std::ops::Mul;
 use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
 
 use num_bigint::BigUint;
 
@@ -25,6 +26,15 @@ impl U256 {
         ])
     }
 
+    pub fn saturating_add(self, rhs: U256) -> U256 {
+        let result = self + rhs;
+        if result < self {
+            U256::MAX
+        } else {
+            result
+        }
+    }
+
     pub fn to_decimal(self) -> String {
         let value = BigUint::from(self.0[0]) << 128 | BigUint::from(self.0[1]);
         format!("{value}")
@@ -48,6 +58,11 @@ impl U256 {
         }
     }
 
+    /// The nearest floating point number, e.g. for statistics.
+    pub fn to_f64(self) -> f64 {
+        self.0[0] as f64 * 2f64.powi(128) + self.0[1] as f64
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut result = Vec::new();
         for i in 0..=1 {
@@ -78,7 +93,7 @@ impl From<&str> for U256 {
             let low_hex = &item[low_start..];
             // disallow + and - prefixes
             assert!(
-                low_hex.as_bytes().first() != Some(&54) && low_hex.as_bytes().first() != Some(&43)
+                low_hex.as_bytes().first() != Some(&45) && low_hex.as_bytes().first() != Some(&43)
             );
             let low = if low_hex.is_empty() {
                 0
@@ -111,6 +126,28 @@ impl From<&str> for U256 {
     }
 }
 
+impl FromStr for U256 {
+    type Err = String;
+
+    /// Parses a hex (with `0x` prefix) or decimal number,
+    /// returning an error instead of panicking.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = match s.strip_prefix("0x") {
+            Some(hex) => hex.len() <= 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()),
+            None => {
+                !s.is_empty()
+                    && s.bytes().all(|b| b.is_ascii_digit())
+                    && s.parse::<BigUint>().unwrap() <= U256::MAX.into()
+            }
+        };
+        if valid {
+            Ok(U256::from(s))
+        } else {
+            Err(format!("Invalid 256 bit number: {s}"))
+        }
+    }
+}
+
 impl From<U256> for BigUint {
     fn from(value: U256) -> Self {
         BigUint::from(value.0[0]) << 128 | BigUint::from(value.0[1])
@@ -226,6 +263,7 @@ mod test {
         assert_eq!(U256::from("0x"), U256::from(0));
         assert_eq!(U256::from("0x1"), U256::from(1));
         assert_eq!(U256::from("0x01"), U256::from(1));
+        assert_eq!(U256::from("0x64"), U256::from(100));
         assert_eq!(
             U256::from("0x1fffffffffffffffffffffffffffffffe"),
             U256::from(u128::MAX) + U256::from(u128::MAX)
@@ -240,6 +278,12 @@ mod test {
         );
     }
 
+    #[test]
+    #[should_panic]
+    fn negative_hex() {
+        let _ = U256::from("0x-1");
+    }
+
     #[test]
     fn from_decimal() {
         assert_eq!(U256::from("0"), U256::from(0));
@@ -258,6 +302,26 @@ mod test {
         );
     }
 
+    #[test]
+    fn from_str() {
+        assert_eq!("0x64".parse::<U256>(), Ok(U256::from(100)));
+        assert_eq!("100".parse::<U256>(), Ok(U256::from(100)));
+        assert_eq!(
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+                .parse::<U256>(),
+            Ok(U256::MAX)
+        );
+        assert!(
+            "115792089237316195423570985008687907853269984665640564039457584007913129639936"
+                .parse::<U256>()
+                .is_err()
+        );
+        assert!("".parse::<U256>().is_err());
+        assert!("-1".parse::<U256>().is_err());
+        assert!("0x-1".parse::<U256>().is_err());
+        assert!("12a".parse::<U256>().is_err());
+    }
+
     #[test]
     fn to_decimal() {
         assert_eq!(U256::from("0").to_decimal(), "0");
@@ -0,0 +1,123 @@
+use pathfinder2::io::{read_edges_binary_from, write_edges_binary_to};
+use pathfinder2::safe_db::graphql::GraphQLSource;
+use pathfinder2::safe_db::source::import_from_source;
+use pathfinder2::types::{Address, U256};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const A: &str = "0x11c7e86ff693e9032a0f41711b5581a04b26be2e";
+const B: &str = "0x22cedde51198d1773590311e2a340dc06b24cb37";
+const C: &str = "0x33cedde51198d1773590311e2a340dc06b24cb37";
+
+fn safe(id: &str, trusts: &[&str], balance: u128) -> String {
+    let outgoing = trusts
+        .iter()
+        .map(|send_to| {
+            format!(r#"{{"limit": null, "limitPercentage": "100", "canSendToAddress": "{send_to}", "userAddress": "{id}"}}"#)
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        r#"{{"id": "{id}", "organization": false, "outgoing": [{outgoing}], "incoming": [],
+        "balances": [{{"amount": "{balance}", "token": {{"id": "{id}", "owner": {{"id": "{id}"}}}}}}]}}"#
+    )
+}
+
+/// Serves the safes a, b, c in pages of two, failing every first request for a page.
+fn mock_subgraph() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/subgraph", listener.local_addr().unwrap());
+    let queries = Arc::new(Mutex::new(Vec::<String>::new()));
+    let q = queries.clone();
+    thread::spawn(move || {
+        for socket in listener.incoming() {
+            let mut socket = socket.unwrap();
+            let query = read_body(&mut socket);
+            let retry = !q.lock().unwrap().contains(&query);
+            q.lock().unwrap().push(query.clone());
+            let response = if retry {
+                r#"{"errors": [{"message": "indexer unavailable"}]}"#.to_string()
+            } else if query.contains(r#"id_gt: \"\""#) {
+                format!(
+                    r#"{{"data": {{"safes": [{}, {}]}}}}"#,
+                    safe(A, &[B], 100),
+                    safe(B, &[C], 50)
+                )
+            } else if query.contains(&format!(r#"id_gt: \"{B}\""#)) {
+                format!(r#"{{"data": {{"safes": [{}]}}}}"#, safe(C, &[], 10))
+            } else {
+                r#"{"data": {"safes": []}}"#.to_string()
+            };
+            write!(
+                socket,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+        }
+    });
+    (url, queries)
+}
+
+fn read_body(socket: &mut TcpStream) -> String {
+    let mut reader = BufReader::new(socket);
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some(l) = line.to_lowercase().strip_prefix("content-length: ") {
+            length = l.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    String::from_utf8(body).unwrap()
+}
+
+#[test]
+fn fetch_to_edges() {
+    let (url, queries) = mock_subgraph();
+    let mut source = GraphQLSource::new(&url)
+        .with_page_size(2)
+        .with_retries(3, Duration::from_millis(1));
+    let db = import_from_source(&mut source).unwrap();
+    assert_eq!(db.safes().len(), 3);
+    assert_eq!(source.last_id(), C);
+    // Three pages, each requested twice.
+    assert_eq!(queries.lock().unwrap().len(), 6);
+
+    let mut buffer = Vec::new();
+    write_edges_binary_to(db.edges(), &mut buffer).unwrap();
+    let edges = read_edges_binary_from(buffer.as_slice()).unwrap();
+    let a = Address::from(A);
+    let outgoing = edges.outgoing(&a);
+    assert_eq!(outgoing.len(), 1);
+    assert_eq!(outgoing[0].to, Address::from(B));
+    assert_eq!(outgoing[0].capacity, U256::from(50));
+}
+
+#[test]
+fn fetch_resume() {
+    let (url, queries) = mock_subgraph();
+    let mut source = GraphQLSource::new(&url)
+        .resume_after(B)
+        .with_retries(1, Duration::from_millis(1));
+    let db = import_from_source(&mut source).unwrap();
+    assert_eq!(db.safes().len(), 1);
+    assert!(db.safes().contains_key(&Address::from(C)));
+    assert!(queries.lock().unwrap()[0].contains(B));
+}
+
+#[test]
+fn fetch_gives_up() {
+    let (url, _) = mock_subgraph();
+    let mut source = GraphQLSource::new(&url).with_retries(0, Duration::from_millis(1));
+    assert!(import_from_source(&mut source).is_err());
+}